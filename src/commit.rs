@@ -0,0 +1,141 @@
+//! Commits: full snapshots of the index plus metadata, one JSON object per line of
+//! `.minigit/commits.jsonl`.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Context, Error, Result};
+use crate::index::{FileEntry, Snapshot, MODE_FILE};
+use crate::objects::ObjectStore;
+
+/// One line of `.minigit/commits.jsonl`: a full snapshot of the index plus metadata.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Commit {
+    pub id: String,
+    pub parent: Option<String>,
+    /// The other parents of a merge commit, after `parent`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub merge_parents: Vec<String>,
+    pub author: String,
+    /// RFC 3339 timestamp with the author's local offset.
+    pub timestamp: String,
+    pub message: String,
+    /// path -> blob_id, sorted so the same snapshot always serializes the same way.
+    pub tree: BTreeMap<String, String>,
+    /// path -> mode for everything that isn't a regular file (executables, symlinks).
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub modes: BTreeMap<String, u32>,
+}
+
+/// Everything a commit id is derived from (i.e., the commit minus its id).
+#[derive(Serialize)]
+struct CommitBody<'a> {
+    parent: &'a Option<String>,
+    #[serde(skip_serializing_if = "<[String]>::is_empty")]
+    merge_parents: &'a [String],
+    author: &'a str,
+    timestamp: &'a str,
+    message: &'a str,
+    tree: &'a BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    modes: &'a BTreeMap<String, u32>,
+}
+
+impl Commit {
+    /// Build a commit and derive its id by hashing the serialized body.
+    /// In git-compatible repos the id is that of a real git commit object, which
+    /// (along with its tree objects) is written to `store` here.
+    /// `parents` is empty for a root commit and has two or more entries for a merge.
+    pub fn new(
+        store: &ObjectStore,
+        parents: Vec<String>,
+        author: String,
+        message: String,
+        snapshot: &Snapshot,
+    ) -> Result<Self> {
+        let timestamp = chrono::Local::now().to_rfc3339();
+        let tree = snapshot.iter().map(|(p, e)| (p.clone(), e.blob.clone())).collect();
+        let modes = snapshot.iter().filter(|(_, e)| e.mode != MODE_FILE).map(|(p, e)| (p.clone(), e.mode)).collect();
+        let id = if store.is_git_compatible() {
+            store.write_git_commit(&parents, &author, &timestamp, &message, snapshot)?
+        } else {
+            let body = CommitBody {
+                parent: &parents.first().cloned(),
+                merge_parents: parents.get(1..).unwrap_or_default(),
+                author: &author, timestamp: &timestamp, message: &message, tree: &tree,
+                modes: &modes,
+            };
+            store.format().hash_hex(&serde_json::to_vec(&body).with_context(|| "serializing commit")?)
+        };
+        let mut parents = parents.into_iter();
+        let parent = parents.next();
+        Ok(Commit { id, parent, merge_parents: parents.collect(), author, timestamp, message, tree, modes })
+    }
+
+    /// The recorded files with their modes.
+    pub fn snapshot(&self) -> Snapshot {
+        self.tree
+            .iter()
+            .map(|(p, blob)| {
+                let mode = self.modes.get(p).copied().unwrap_or(MODE_FILE);
+                (p.clone(), FileEntry { mode, blob: blob.clone() })
+            })
+            .collect()
+    }
+
+    /// First line of the message.
+    pub fn subject(&self) -> &str {
+        self.message.lines().next().unwrap_or("")
+    }
+
+    /// Parent ids in order (none for a root commit).
+    pub fn parents(&self) -> Vec<&str> {
+        self.parent.iter().chain(&self.merge_parents).map(String::as_str).collect()
+    }
+
+    /// Did this commit change `path` (a file, or any file under a directory) relative to `parent`?
+    pub fn touches_path(&self, parent: Option<&Commit>, path: &str) -> bool {
+        let under = |p: &String| p == path || p.starts_with(&format!("{path}/"));
+        let before = parent.map(|p| p.snapshot()).unwrap_or_default();
+        let after = self.snapshot();
+        let mine = after.iter().filter(|(p, _)| under(p));
+        let theirs = before.iter().filter(|(p, _)| under(p));
+        !mine.eq(theirs)
+    }
+}
+
+/// Look up a commit by full id or unique id prefix.
+pub fn find_commit(commits: &[Commit], rev: &str) -> Result<Commit> {
+    let matches: Vec<&Commit> = commits.iter().filter(|c| c.id.starts_with(rev)).collect();
+    match matches.as_slice() {
+        [c] => Ok((*c).clone()),
+        [] => Err(Error::UnknownRevision(rev.to_string())),
+        _ => Err(Error::AmbiguousRevision {
+            prefix: rev.to_string(),
+            candidates: matches.iter().map(|c| c.id.clone()).collect(),
+        }),
+    }
+}
+
+/// `ident` stamped with a time the way git writes it: "Name <email> <unix seconds> <+hhmm>".
+pub fn ident_line(ident: &str, when: &chrono::DateTime<chrono::FixedOffset>) -> String {
+    format!("{ident} {} {}", when.timestamp(), when.format("%z"))
+}
+
+/// Split an [`ident_line`] back into the ident and its time.
+pub fn parse_ident_line(line: &str) -> Option<(&str, chrono::DateTime<chrono::FixedOffset>)> {
+    let mut parts = line.rsplitn(3, ' ');
+    let (tz, secs, ident) = (parts.next()?, parts.next()?, parts.next()?);
+    let when = chrono::DateTime::parse_from_str(&format!("{secs} {tz}"), "%s %z").ok()?;
+    Some((ident, when))
+}
+
+/// "Name <email>" from MINIGIT_AUTHOR_NAME / MINIGIT_AUTHOR_EMAIL, falling back to $USER.
+pub fn author_ident() -> String {
+    let name = std::env::var("MINIGIT_AUTHOR_NAME")
+        .or_else(|_| std::env::var("USER"))
+        .unwrap_or_else(|_| "unknown".to_string());
+    let email = std::env::var("MINIGIT_AUTHOR_EMAIL").unwrap_or_else(|_| format!("{name}@localhost"));
+    format!("{name} <{email}>")
+}
@@ -0,0 +1,762 @@
+//! Line and word diffs over raw bytes, shared by `diff`, `add -p` and anything else that
+//! needs to line up two versions of a file (merge, blame, patch application).
+//!
+//! Three algorithms are offered, like git's `--diff-algorithm`:
+//! * **Myers**: the classic O(ND) shortest edit script.
+//! * **Patience**: anchors on lines that occur exactly once on both sides, which keeps
+//!   moved blocks and braces from being matched up with the wrong lines.
+//! * **Histogram**: anchors on the rarest common line instead (so it still finds anchors
+//!   when nothing is unique); usually the most readable output, and git's favorite.
+//!
+//! Whichever is used, replaying the script reproduces the new text: every line of
+//! `a` appears once as `Equal` or `Delete`, every line of `b` once as `Equal` or `Insert`,
+//! both in order.
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// How a line (or word) relates the old text to the new one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Op {
+    Equal,
+    Delete,
+    Insert,
+}
+
+/// One step of an edit script. `old` / `new` index the old and new lines: for `Equal`
+/// both are the matched lines; for `Delete` only `old` names a line (`new` is where it
+/// would go), and for `Insert` only `new` does.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Edit {
+    pub op: Op,
+    pub old: usize,
+    pub new: usize,
+}
+
+impl Edit {
+    /// The line this step shows: the old one, except for insertions. (With whitespace
+    /// ignored, an `Equal` pair may differ; like git, the old version is shown.)
+    pub fn text<'a>(&self, a: &[&'a [u8]], b: &[&'a [u8]]) -> &'a [u8] {
+        match self.op {
+            Op::Insert => b[self.new],
+            _ => a[self.old],
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Algorithm {
+    #[default]
+    Myers,
+    Patience,
+    Histogram,
+}
+
+impl std::str::FromStr for Algorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "myers" | "default" => Ok(Algorithm::Myers),
+            "patience" => Ok(Algorithm::Patience),
+            "histogram" => Ok(Algorithm::Histogram),
+            _ => Err(format!("unknown diff algorithm {s:?} (expected myers, patience or histogram)")),
+        }
+    }
+}
+
+/// Which whitespace differences don't count when comparing lines.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Whitespace {
+    #[default]
+    Exact,
+    /// `--ignore-space-at-eol`: trailing whitespace
+    IgnoreAtEol,
+    /// `-b`: changes in the amount of whitespace (but not its presence inside a line)
+    IgnoreChange,
+    /// `-w`: all whitespace
+    IgnoreAll,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Options {
+    pub algorithm: Algorithm,
+    pub whitespace: Whitespace,
+}
+
+/* -------- lines -------- */
+
+/// Lines of `data`, each keeping its `\n` (the last one may lack it).
+pub fn split_lines(data: &[u8]) -> Vec<&[u8]> {
+    data.split_inclusive(|&b| b == b'\n').collect()
+}
+
+/// Like git: a NUL byte near the start means "binary, don't show a line diff".
+pub fn is_binary(data: &[u8]) -> bool {
+    data[..data.len().min(8000)].contains(&0)
+}
+
+/// The part of a line that takes part in comparisons under `ws`.
+fn normalize(line: &[u8], ws: Whitespace) -> Vec<u8> {
+    let trimmed = line.trim_ascii_end();
+    match ws {
+        Whitespace::Exact => line.to_vec(),
+        Whitespace::IgnoreAtEol => trimmed.to_vec(),
+        Whitespace::IgnoreAll => trimmed.iter().copied().filter(|c| !c.is_ascii_whitespace()).collect(),
+        Whitespace::IgnoreChange => {
+            let mut out = Vec::with_capacity(trimmed.len());
+            for &c in trimmed {
+                if !c.is_ascii_whitespace() {
+                    out.push(c);
+                } else if out.last() != Some(&b' ') {
+                    out.push(b' '); // any run of whitespace counts as one space
+                }
+            }
+            out
+        }
+    }
+}
+
+/// Diff two texts line by line.
+pub fn diff_lines(a: &[&[u8]], b: &[&[u8]], opts: &Options) -> Vec<Edit> {
+    // Intern lines as small integers so the algorithms compare ids, not bytes
+    let mut ids: HashMap<Vec<u8>, usize> = HashMap::new();
+    let mut intern = |line: &&[u8]| {
+        let next = ids.len();
+        *ids.entry(normalize(line, opts.whitespace)).or_insert(next)
+    };
+    let a: Vec<usize> = a.iter().map(&mut intern).collect();
+    let b: Vec<usize> = b.iter().map(&mut intern).collect();
+    diff_ids(&a, &b, opts.algorithm)
+}
+
+/// The edit script between two id sequences (lines or words) with the chosen algorithm.
+fn diff_ids(a: &[usize], b: &[usize], algorithm: Algorithm) -> Vec<Edit> {
+    let mut out = Vec::with_capacity(a.len().max(b.len()));
+    let (ar, br) = (0..a.len(), 0..b.len());
+    match algorithm {
+        Algorithm::Myers => myers(a, b, ar, br, &mut out),
+        Algorithm::Patience => patience(a, b, ar, br, &mut out),
+        Algorithm::Histogram => histogram(a, b, ar, br, &mut out),
+    }
+    slide_down(a, b, &mut out);
+    out
+}
+
+/// Move each run of insertions (or of deletions) as far down as it can go without changing
+/// the result, like git: when the same lines could be added before or after a repeated
+/// line (a closing brace, a blank line), the added block then ends with it, not starts.
+fn slide_down(a: &[usize], b: &[usize], edits: &mut [Edit]) {
+    let mut i = 0;
+    while i < edits.len() {
+        let op = edits[i].op;
+        let mut j = i + edits[i..].iter().take_while(|e| e.op == op).count();
+        if op == Op::Equal {
+            i = j;
+            continue;
+        }
+        // while the line after the run equals its first line, that line can be matched at the
+        // top of the run instead and the run shifts down by one (its lines are consecutive)
+        while let Some(&eq) = edits.get(j).filter(|e| e.op == Op::Equal) {
+            let top = edits[i];
+            match op {
+                Op::Insert if b[top.new] == b[eq.new] => {
+                    edits[i] = Edit { op: Op::Equal, old: eq.old, new: top.new };
+                    for (n, e) in edits[i + 1..=j].iter_mut().enumerate() {
+                        *e = Edit { op, old: eq.old + 1, new: top.new + 1 + n };
+                    }
+                }
+                Op::Delete if a[top.old] == a[eq.old] => {
+                    edits[i] = Edit { op: Op::Equal, old: top.old, new: eq.new };
+                    for (n, e) in edits[i + 1..=j].iter_mut().enumerate() {
+                        *e = Edit { op, old: top.old + 1 + n, new: eq.new + 1 };
+                    }
+                }
+                _ => break,
+            }
+            i += 1;
+            j += 1;
+        }
+        i = j;
+    }
+}
+
+/* -------- algorithms -------- */
+/* Each takes the ranges of `a` and `b` still to be diffed and appends their edits to `out`. */
+
+/// Emit the common prefix of the two ranges as `Equal` and shrink them past it and past the
+/// common suffix; returns the suffix length (to emit after the middle).
+fn trim_common(a: &[usize], b: &[usize], ar: &mut Range<usize>, br: &mut Range<usize>, out: &mut Vec<Edit>) -> usize {
+    while ar.start < ar.end && br.start < br.end && a[ar.start] == b[br.start] {
+        out.push(Edit { op: Op::Equal, old: ar.start, new: br.start });
+        ar.start += 1;
+        br.start += 1;
+    }
+    let mut suffix = 0;
+    while ar.start < ar.end && br.start < br.end && a[ar.end - 1] == b[br.end - 1] {
+        ar.end -= 1;
+        br.end -= 1;
+        suffix += 1;
+    }
+    suffix
+}
+
+fn push_suffix(ar: &Range<usize>, br: &Range<usize>, suffix: usize, out: &mut Vec<Edit>) {
+    out.extend((0..suffix).map(|i| Edit { op: Op::Equal, old: ar.end + i, new: br.end + i }));
+}
+
+/// Myers' O(ND) algorithm: a shortest edit script for `a[ar]` → `b[br]`, in linear space:
+/// find the "middle snake" of an optimal path, then solve the boxes before and after it.
+fn myers(a: &[usize], b: &[usize], mut ar: Range<usize>, mut br: Range<usize>, out: &mut Vec<Edit>) {
+    let suffix = trim_common(a, b, &mut ar, &mut br, out);
+    if ar.is_empty() {
+        out.extend(br.clone().map(|new| Edit { op: Op::Insert, old: ar.start, new }));
+    } else if br.is_empty() {
+        out.extend(ar.clone().map(|old| Edit { op: Op::Delete, old, new: br.start }));
+    } else {
+        let (x0, y0, x1, y1) = middle_snake(&a[ar.clone()], &b[br.clone()]);
+        let (x0, y0, x1, y1) = (ar.start + x0, br.start + y0, ar.start + x1, br.start + y1);
+        myers(a, b, ar.start..x0, br.start..y0, out);
+        out.extend((x0..x1).zip(y0..y1).map(|(old, new)| Edit { op: Op::Equal, old, new }));
+        myers(a, b, x1..ar.end, y1..br.end, out);
+    }
+    push_suffix(&ar, &br, suffix, out);
+}
+
+/// The snake (run of equal lines, possibly empty) in the middle of a shortest edit path
+/// from (0, 0) to (n, m), as `(x0, y0, x1, y1)`: search forward from the start and
+/// backward from the end, one edit at a time, until the two frontiers overlap.
+fn middle_snake(a: &[usize], b: &[usize]) -> (usize, usize, usize, usize) {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let delta = n - m;
+    let odd = delta % 2 != 0;
+    let max = (n + m + 1) / 2;
+    let idx = |k: isize| (k + max + 1) as usize;
+    // forward[k] / backward[k]: furthest x on diagonal k (x - y = k), measured from the start /
+    // from the end; backward diagonal k meets forward diagonal delta - k
+    let mut forward = vec![0isize; 2 * max as usize + 3];
+    let mut backward = vec![0isize; 2 * max as usize + 3];
+    for d in 0..=max {
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && forward[idx(k - 1)] < forward[idx(k + 1)]) {
+                forward[idx(k + 1)]
+            } else {
+                forward[idx(k - 1)] + 1
+            };
+            let (x0, y0) = (x, x - k);
+            let mut y = y0;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            forward[idx(k)] = x;
+            if odd && (delta - k).abs() < d && x + backward[idx(delta - k)] >= n {
+                return (x0 as usize, y0 as usize, x as usize, y as usize);
+            }
+        }
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && backward[idx(k - 1)] < backward[idx(k + 1)]) {
+                backward[idx(k + 1)]
+            } else {
+                backward[idx(k - 1)] + 1
+            };
+            let (x0, y0) = (x, x - k);
+            let mut y = y0;
+            while x < n && y < m && a[(n - x - 1) as usize] == b[(m - y - 1) as usize] {
+                x += 1;
+                y += 1;
+            }
+            backward[idx(k)] = x;
+            if !odd && (delta - k).abs() <= d && x + forward[idx(delta - k)] >= n {
+                return ((n - x) as usize, (m - y) as usize, (n - x0) as usize, (m - y0) as usize);
+            }
+        }
+    }
+    unreachable!("the forward and backward searches always meet by d = (n + m) / 2")
+}
+
+/// Patience diff: match up lines that are unique in both ranges, keep the longest run of
+/// them that's in the same order on both sides, and recurse between those anchors.
+/// Falls back to Myers when there are no unique common lines.
+fn patience(a: &[usize], b: &[usize], mut ar: Range<usize>, mut br: Range<usize>, out: &mut Vec<Edit>) {
+    let suffix = trim_common(a, b, &mut ar, &mut br, out);
+
+    // 1) lines occurring exactly once on each side: (position in a, position in b)
+    let mut seen: HashMap<usize, (usize, usize, usize)> = HashMap::new(); // id -> (count a, count b, pos a)
+    for i in ar.clone() {
+        let e = seen.entry(a[i]).or_insert((0, 0, i));
+        e.0 += 1;
+    }
+    let mut unique = Vec::new();
+    for j in br.clone() {
+        if let Some(e) = seen.get_mut(&b[j]) {
+            e.1 += 1;
+        }
+    }
+    for j in br.clone() {
+        if let Some(&(1, 1, i)) = seen.get(&b[j]) {
+            unique.push((i, j));
+        }
+    }
+    unique.sort_unstable();
+
+    // 2) longest increasing subsequence by position in b (patience sorting)
+    let anchors = longest_increasing(&unique);
+    if anchors.is_empty() {
+        myers(a, b, ar.clone(), br.clone(), out);
+        push_suffix(&ar, &br, suffix, out);
+        return;
+    }
+
+    // 3) diff the gaps between anchors recursively
+    let (mut i, mut j) = (ar.start, br.start);
+    for (ai, bj) in anchors {
+        patience(a, b, i..ai, j..bj, out);
+        out.push(Edit { op: Op::Equal, old: ai, new: bj });
+        (i, j) = (ai + 1, bj + 1);
+    }
+    patience(a, b, i..ar.end, j..br.end, out);
+    push_suffix(&ar, &br, suffix, out);
+}
+
+/// The longest subsequence of `pairs` (sorted by `.0`) whose `.1` values increase.
+fn longest_increasing(pairs: &[(usize, usize)]) -> Vec<(usize, usize)> {
+    // tails[k] = index of the smallest-ending run of length k+1; prev links rebuild the run
+    let mut tails: Vec<usize> = Vec::new();
+    let mut prev = vec![None; pairs.len()];
+    for (n, &(_, j)) in pairs.iter().enumerate() {
+        let k = tails.partition_point(|&t| pairs[t].1 < j);
+        if k > 0 {
+            prev[n] = Some(tails[k - 1]);
+        }
+        if k == tails.len() {
+            tails.push(n);
+        } else {
+            tails[k] = n;
+        }
+    }
+    let mut out = Vec::new();
+    let mut cur = tails.last().copied();
+    while let Some(n) = cur {
+        out.push(pairs[n]);
+        cur = prev[n];
+    }
+    out.reverse();
+    out
+}
+
+/// Histogram diff (after JGit's): anchor on the longest common region around the line
+/// that occurs least often in `a`, then recurse on both sides of it. Lines repeated more
+/// than `MAX_CHAIN` times aren't used as anchors; if nothing qualifies, fall back to Myers.
+fn histogram(a: &[usize], b: &[usize], mut ar: Range<usize>, mut br: Range<usize>, out: &mut Vec<Edit>) {
+    const MAX_CHAIN: usize = 64;
+    let suffix = trim_common(a, b, &mut ar, &mut br, out);
+    if ar.is_empty() || br.is_empty() {
+        myers(a, b, ar.clone(), br.clone(), out); // only deletions or insertions left
+        push_suffix(&ar, &br, suffix, out);
+        return;
+    }
+
+    // 1) where each line occurs in a
+    let mut occurrences: HashMap<usize, Vec<usize>> = HashMap::new();
+    for i in ar.clone() {
+        occurrences.entry(a[i]).or_default().push(i);
+    }
+
+    // 2) for every b line also in a, grow the common region around each match; keep the
+    //    region anchored on the rarest line, longest first among equally rare ones
+    let mut best: Option<(usize, Range<usize>, Range<usize>)> = None; // (count, a region, b region)
+    for j in br.clone() {
+        let Some(hits) = occurrences.get(&b[j]) else { continue };
+        if hits.len() > MAX_CHAIN || best.as_ref().is_some_and(|(count, ..)| hits.len() > *count) {
+            continue;
+        }
+        for &i in hits {
+            let (mut s_a, mut s_b) = (i, j);
+            while s_a > ar.start && s_b > br.start && a[s_a - 1] == b[s_b - 1] {
+                s_a -= 1;
+                s_b -= 1;
+            }
+            let (mut e_a, mut e_b) = (i + 1, j + 1);
+            while e_a < ar.end && e_b < br.end && a[e_a] == b[e_b] {
+                e_a += 1;
+                e_b += 1;
+            }
+            // rarest anchor first, then the longest region, then the one nearest the middle
+            // (which keeps the recursion balanced when every line is unique)
+            let mid = (ar.start + ar.end) / 2;
+            let better = match &best {
+                None => true,
+                Some((count, region, _)) => {
+                    (hits.len(), std::cmp::Reverse(e_a - s_a), s_a.abs_diff(mid))
+                        < (*count, std::cmp::Reverse(region.len()), region.start.abs_diff(mid))
+                }
+            };
+            if better {
+                best = Some((hits.len(), s_a..e_a, s_b..e_b));
+            }
+        }
+    }
+
+    // 3) recurse around the region, or give up on anchors
+    match best {
+        Some((_, ra, rb)) => {
+            histogram(a, b, ar.start..ra.start, br.start..rb.start, out);
+            out.extend(ra.clone().zip(rb.clone()).map(|(old, new)| Edit { op: Op::Equal, old, new }));
+            histogram(a, b, ra.end..ar.end, rb.end..br.end, out);
+        }
+        None => myers(a, b, ar.clone(), br.clone(), out),
+    }
+    push_suffix(&ar, &br, suffix, out);
+}
+
+/* -------- hunks -------- */
+
+/// Group changed lines into hunks with up to `context` unchanged lines around them
+/// (ranges index into `ops`). Changes closer than 2 × context share a hunk.
+pub fn hunk_ranges(ops: &[Op], context: usize) -> Vec<Range<usize>> {
+    let mut out: Vec<Range<usize>> = Vec::new();
+    for (i, op) in ops.iter().enumerate() {
+        if *op == Op::Equal {
+            continue;
+        }
+        let start = i.saturating_sub(context);
+        let end = (i + 1 + context).min(ops.len());
+        match out.last_mut() {
+            Some(last) if start <= last.end => last.end = end,
+            _ => out.push(start..end),
+        }
+    }
+    out
+}
+
+/// `@@ -old_start,old_len +new_start,new_len @@` for the lines `ops[range]`.
+pub fn hunk_header(ops: &[Op], range: Range<usize>) -> String {
+    let count = |ops: &[Op], skip: Op| ops.iter().filter(|op| **op != skip).count();
+    let side = |skip: Op| {
+        let (before, len) = (count(&ops[..range.start], skip), count(&ops[range.clone()], skip));
+        // an empty side names the line *before* the hunk, like diff(1)
+        let start = if len == 0 { before } else { before + 1 };
+        if len == 1 { format!("{start}") } else { format!("{start},{len}") }
+    };
+    format!("@@ -{} +{} @@", side(Op::Insert), side(Op::Delete))
+}
+
+/* -------- words -------- */
+
+/// Byte ranges of the words of `data`: runs of non-whitespace, as git splits them by default.
+fn words(data: &[u8]) -> Vec<Range<usize>> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        if data[i].is_ascii_whitespace() {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < data.len() && !data[i].is_ascii_whitespace() {
+            i += 1;
+        }
+        out.push(start..i);
+    }
+    out
+}
+
+/// Word-level diff of two texts, as slices that concatenate to a display of the change:
+/// `Equal` text (spacing taken from `b`), and each run of changes as one `Delete` slice of
+/// `a` followed by one `Insert` slice of `b`. Like `git diff --word-diff`, changes in
+/// whitespace alone don't show up.
+pub fn diff_words<'a>(a: &'a [u8], b: &'a [u8], opts: &Options) -> Vec<(Op, &'a [u8])> {
+    let (wa, wb) = (words(a), words(b));
+    let ta: Vec<&[u8]> = wa.iter().map(|r| &a[r.clone()]).collect();
+    let tb: Vec<&[u8]> = wb.iter().map(|r| &b[r.clone()]).collect();
+    let edits = diff_lines(&ta, &tb, &Options { whitespace: Whitespace::Exact, ..*opts });
+
+    let mut out = Vec::new();
+    let (mut pa, mut pb) = (0, 0); // end of the last token shown from each side
+    let mut i = 0;
+    while i < edits.len() {
+        let e = edits[i];
+        if e.op == Op::Equal {
+            // the spacing before the word as it is now, then the word
+            out.push((Op::Equal, &b[pb..wb[e.new].end]));
+            (pa, pb) = (wa[e.old].end, wb[e.new].end);
+            i += 1;
+            continue;
+        }
+        // a run of changes: old words with their old spacing, then new words with theirs
+        let end = edits[i..].iter().position(|e| e.op == Op::Equal).map_or(edits.len(), |n| i + n);
+        let run = &edits[i..end];
+        let dels: Vec<&Range<usize>> = run.iter().filter(|e| e.op == Op::Delete).map(|e| &wa[e.old]).collect();
+        let ins: Vec<&Range<usize>> = run.iter().filter(|e| e.op == Op::Insert).map(|e| &wb[e.new]).collect();
+        match (ins.first(), dels.first()) {
+            (Some(first), _) => out.push((Op::Equal, &b[pb..first.start])),
+            (None, Some(first)) => out.push((Op::Equal, &a[pa..first.start])),
+            (None, None) => {}
+        }
+        if let (Some(first), Some(last)) = (dels.first(), dels.last()) {
+            out.push((Op::Delete, &a[first.start..last.end]));
+            pa = last.end;
+        }
+        if let (Some(first), Some(last)) = (ins.first(), ins.last()) {
+            out.push((Op::Insert, &b[first.start..last.end]));
+            pb = last.end;
+        }
+        i = end;
+    }
+    out.push((Op::Equal, &b[pb..]));
+    out.retain(|(_, text)| !text.is_empty());
+    out
+}
+
+/* -------- three-way merge -------- */
+
+/// How conflicting hunks are written, like git's `merge.conflictStyle`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ConflictStyle {
+    /// `<<<<<<< ours`, `=======`, `>>>>>>> theirs`
+    #[default]
+    Merge,
+    /// Also shows the base version between `|||||||` and `=======`.
+    Diff3,
+}
+
+/// Names shown after the conflict markers.
+#[derive(Clone, Copy, Debug)]
+pub struct MergeLabels<'a> {
+    pub ours: &'a str,
+    pub base: &'a str,
+    pub theirs: &'a str,
+}
+
+/// The outcome of [`merge3`]: the merged text, with conflict markers around each hunk
+/// both sides changed differently.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Merged {
+    pub text: Vec<u8>,
+    pub conflicts: usize,
+}
+
+/// Three-way merge of line-based texts: changes only one side made are taken, identical
+/// changes on both sides are taken once, and overlapping different changes become
+/// conflicts (diff3's algorithm over two line diffs against `base`).
+pub fn merge3(base: &[u8], ours: &[u8], theirs: &[u8], labels: MergeLabels, style: ConflictStyle) -> Merged {
+    let (b, o, t) = (split_lines(base), split_lines(ours), split_lines(theirs));
+
+    // 1) which line of each side every base line is kept as
+    let matches = |side: &[&[u8]]| {
+        let mut at = vec![None; b.len()];
+        for e in diff_lines(&b, side, &Options::default()) {
+            if e.op == Op::Equal {
+                at[e.old] = Some(e.new);
+            }
+        }
+        at
+    };
+    let (in_ours, in_theirs) = (matches(&o), matches(&t));
+
+    // 2) alternate between stable runs (a base line kept by both sides, where both expect
+    //    it) and unstable chunks up to the next base line both sides kept
+    let mut merged = Merged { text: Vec::new(), conflicts: 0 };
+    let (mut ib, mut io, mut it) = (0, 0, 0);
+    loop {
+        while ib < b.len() && in_ours[ib] == Some(io) && in_theirs[ib] == Some(it) {
+            merged.text.extend_from_slice(b[ib]);
+            (ib, io, it) = (ib + 1, io + 1, it + 1);
+        }
+        if ib == b.len() && io == o.len() && it == t.len() {
+            break;
+        }
+        let sync = (ib..b.len()).find_map(|i| Some((i, in_ours[i]?, in_theirs[i]?)));
+        let (nb, no, nt) = sync.unwrap_or((b.len(), o.len(), t.len()));
+        let (cb, co, ct) = (&b[ib..nb], &o[io..no], &t[it..nt]);
+        if co == cb || co == ct {
+            ct.iter().for_each(|l| merged.text.extend_from_slice(l));
+        } else if ct == cb {
+            co.iter().for_each(|l| merged.text.extend_from_slice(l));
+        } else {
+            merged.conflicts += 1;
+            // lines both sides start or end the same way go outside the markers (unless the
+            // base is shown, which only makes sense next to whole chunks)
+            let (mut head, mut tail) = (0, 0);
+            if style == ConflictStyle::Merge {
+                head = co.iter().zip(ct).take_while(|(x, y)| x == y).count();
+                let rest = co.len().min(ct.len()) - head;
+                tail = co.iter().rev().zip(ct.iter().rev()).take(rest).take_while(|(x, y)| x == y).count();
+            }
+            let section = |out: &mut Vec<u8>, marker: &str, lines: &[&[u8]]| {
+                if out.last().is_some_and(|&c| c != b'\n') {
+                    out.push(b'\n');
+                }
+                out.extend_from_slice(marker.as_bytes());
+                lines.iter().for_each(|l| out.extend_from_slice(l));
+            };
+            co[..head].iter().for_each(|l| merged.text.extend_from_slice(l));
+            section(&mut merged.text, &format!("<<<<<<< {}\n", labels.ours), &co[head..co.len() - tail]);
+            if style == ConflictStyle::Diff3 {
+                section(&mut merged.text, &format!("||||||| {}\n", labels.base), cb);
+            }
+            section(&mut merged.text, "=======\n", &ct[head..ct.len() - tail]);
+            section(&mut merged.text, &format!(">>>>>>> {}\n", labels.theirs), &[]);
+            co[co.len() - tail..].iter().for_each(|l| merged.text.extend_from_slice(l));
+        }
+        (ib, io, it) = (nb, no, nt);
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// xorshift64*: a small seeded generator, so failures reproduce.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 >> 12;
+            self.0 ^= self.0 << 25;
+            self.0 ^= self.0 >> 27;
+            self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+
+        /// Up to 30 lines drawn from a few distinct ones (so there are plenty of repeats),
+        /// sometimes without a final newline.
+        fn text(&mut self) -> Vec<u8> {
+            let mut out = Vec::new();
+            for _ in 0..self.below(31) {
+                out.extend_from_slice(format!("line {}\n", self.below(6)).as_bytes());
+            }
+            if !out.is_empty() && self.below(4) == 0 {
+                out.pop();
+            }
+            out
+        }
+
+        /// `base` with a few lines deleted, replaced or inserted.
+        fn edit(&mut self, base: &[u8]) -> Vec<u8> {
+            let mut lines: Vec<Vec<u8>> = split_lines(base).into_iter().map(<[u8]>::to_vec).collect();
+            for _ in 0..self.below(4) {
+                let at = self.below(lines.len() + 1);
+                let new = format!("new {}\n", self.below(6)).into_bytes();
+                match self.below(3) {
+                    0 if at < lines.len() => {
+                        lines.remove(at);
+                    }
+                    1 if at < lines.len() => lines[at] = new,
+                    _ => lines.insert(at, new),
+                }
+            }
+            // only the last line may lack its newline
+            let last = lines.len().saturating_sub(1);
+            for line in &mut lines[..last] {
+                if !line.ends_with(b"\n") {
+                    line.push(b'\n');
+                }
+            }
+            lines.concat()
+        }
+    }
+
+    const CASES: usize = 2000;
+    const ALGORITHMS: [Algorithm; 3] = [Algorithm::Myers, Algorithm::Patience, Algorithm::Histogram];
+
+    /// Replay an edit script, checking it walks both texts in order; returns the new text.
+    fn replay(a: &[&[u8]], b: &[&[u8]], script: &[Edit]) -> Vec<u8> {
+        let (mut old, mut new, mut out) = (0, 0, Vec::new());
+        for e in script {
+            match e.op {
+                Op::Equal => {
+                    assert_eq!((e.old, e.new), (old, new));
+                    assert_eq!(a[e.old], b[e.new]);
+                    (old, new) = (old + 1, new + 1);
+                }
+                Op::Delete => {
+                    assert_eq!(e.old, old);
+                    old += 1;
+                    continue;
+                }
+                Op::Insert => {
+                    assert_eq!(e.new, new);
+                    new += 1;
+                }
+            }
+            out.extend_from_slice(e.text(a, b));
+        }
+        assert_eq!((old, new), (a.len(), b.len()), "script doesn't cover both texts");
+        out
+    }
+
+    fn lcs_len(a: &[&[u8]], b: &[&[u8]]) -> usize {
+        let mut row = vec![0; b.len() + 1];
+        for x in a {
+            let mut diag = 0;
+            for (j, y) in b.iter().enumerate() {
+                let above = row[j + 1];
+                row[j + 1] = if x == y { diag + 1 } else { above.max(row[j]) };
+                diag = above;
+            }
+        }
+        row[b.len()]
+    }
+
+    #[test]
+    fn replaying_a_diff_rebuilds_the_new_text() {
+        let mut rng = Rng(0x5eed_0001);
+        for case in 0..CASES {
+            let old = rng.text();
+            let new = if case % 2 == 0 { rng.edit(&old) } else { rng.text() };
+            let (a, b) = (split_lines(&old), split_lines(&new));
+            for algorithm in ALGORITHMS {
+                let script = diff_lines(&a, &b, &Options { algorithm, ..Options::default() });
+                assert_eq!(replay(&a, &b, &script), new, "{algorithm:?} on case {case}");
+            }
+        }
+    }
+
+    #[test]
+    fn myers_is_minimal() {
+        let mut rng = Rng(0x5eed_0002);
+        for case in 0..CASES {
+            let old = rng.text();
+            let new = if case % 2 == 0 { rng.edit(&old) } else { rng.text() };
+            let (a, b) = (split_lines(&old), split_lines(&new));
+            let script = diff_lines(&a, &b, &Options::default());
+            let changes = script.iter().filter(|e| e.op != Op::Equal).count();
+            let common = lcs_len(&a, &b);
+            assert_eq!(changes, a.len() + b.len() - 2 * common, "case {case}");
+        }
+    }
+
+    #[test]
+    fn merge3_takes_the_only_side_that_changed() {
+        let labels = MergeLabels { ours: "ours", base: "base", theirs: "theirs" };
+        let mut rng = Rng(0x5eed_0003);
+        for case in 0..CASES {
+            let base = rng.text();
+            let changed = rng.edit(&base);
+            for style in [ConflictStyle::Merge, ConflictStyle::Diff3] {
+                let theirs = merge3(&base, &base, &changed, labels, style);
+                assert_eq!(theirs, Merged { text: changed.clone(), conflicts: 0 }, "theirs, case {case}");
+                let ours = merge3(&base, &changed, &base, labels, style);
+                assert_eq!(ours, Merged { text: changed.clone(), conflicts: 0 }, "ours, case {case}");
+                let both = merge3(&base, &changed, &changed, labels, style);
+                assert_eq!(both, Merged { text: changed.clone(), conflicts: 0 }, "both, case {case}");
+            }
+        }
+    }
+
+    #[test]
+    fn merge3_marks_overlapping_changes() {
+        let labels = MergeLabels { ours: "ours", base: "base", theirs: "theirs" };
+        let merged = merge3(b"a\nb\nc\n", b"a\nB\nc\n", b"a\nX\nc\n", labels, ConflictStyle::Merge);
+        assert_eq!(merged.conflicts, 1);
+        assert_eq!(merged.text, b"a\n<<<<<<< ours\nB\n=======\nX\n>>>>>>> theirs\nc\n");
+        let merged = merge3(b"a\nb\nc\n", b"a\nB\nc\n", b"a\nX\nc\n", labels, ConflictStyle::Diff3);
+        assert_eq!(merged.text, b"a\n<<<<<<< ours\nB\n||||||| base\nb\n=======\nX\n>>>>>>> theirs\nc\n");
+    }
+}
@@ -0,0 +1,131 @@
+//! The library's error type: one variant per failure a caller may want to tell apart,
+//! plus I/O and JSON errors carrying what was being done when they happened.
+
+use std::path::PathBuf;
+
+use crate::objects::ObjectFormat;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// Discovery found no `.minigit` in the start directory or any parent.
+    #[error("Not a mini-git repo (no .minigit in this directory or any parent). Run `mini-git init` first.")]
+    NoRepository { start: PathBuf },
+
+    /// An explicitly named repository directory doesn't exist.
+    #[error("Not a mini-git repo: {} does not exist", .0.display())]
+    MissingGitDir(PathBuf),
+
+    /// `init` found a repository already there.
+    #[error("{} already exists", .0.display())]
+    AlreadyInitialized(PathBuf),
+
+    /// Another process holds `index.lock`.
+    #[error("unable to lock {}: another mini-git process seems to be running.\nIf it crashed, remove the lock file manually.", .0.display())]
+    Locked(PathBuf),
+
+    #[error("{} is outside the repository at {}", path.display(), worktree.display())]
+    OutsideRepository { path: PathBuf, worktree: PathBuf },
+
+    #[error("pathspec '{pathspec}' did not match any {}files", if *tracked { "tracked " } else { "" })]
+    NoMatch { pathspec: String, tracked: bool },
+
+    #[error("invalid object id {0:?}")]
+    InvalidObjectId(String),
+
+    /// A well-formed id of the wrong length for this repository's hash.
+    #[error(
+        "object id {id} has {} hex digits, but this repository uses {} ids ({} hex digits); object formats can't be mixed",
+        id.len(),
+        format.name(),
+        format.hex_len()
+    )]
+    ObjectFormatMismatch { id: String, format: ObjectFormat },
+
+    #[error("object {0} not found")]
+    ObjectNotFound(String),
+
+    #[error("object {id} is a {kind}, not a {expected}")]
+    WrongObjectKind { id: String, kind: String, expected: &'static str },
+
+    #[error("unknown revision: {0}")]
+    UnknownRevision(String),
+
+    /// An abbreviated id matching more than one object.
+    #[error("short object id {prefix} is ambiguous; it could be any of:\n  {}", candidates.join("\n  "))]
+    AmbiguousRevision { prefix: String, candidates: Vec<String> },
+
+    /// A branch or tag name git wouldn't accept.
+    #[error("'{name}' is not a valid {kind} name")]
+    InvalidRefName { kind: &'static str, name: String },
+
+    #[error("a {kind} named '{name}' already exists")]
+    RefExists { kind: &'static str, name: String },
+
+    #[error("{kind} '{name}' not found")]
+    RefNotFound { kind: &'static str, name: String },
+
+    /// Deleting the branch would lose commits HEAD doesn't contain.
+    #[error("the branch '{0}' is not fully merged.\nIf you are sure you want to delete it, run 'mini-git branch -D {0}'.")]
+    BranchNotMerged(String),
+
+    #[error("nothing to commit ({0})")]
+    NothingToCommit(&'static str),
+
+    /// Checkout would overwrite these paths' uncommitted changes.
+    #[error("checkout would overwrite local changes to:\n  {}\nCommit them, or re-run with --force to discard them.", .0.join("\n  "))]
+    WouldOverwrite(Vec<String>),
+
+    /// A merge would overwrite these paths' uncommitted changes (or untracked files).
+    #[error("merge would overwrite local changes to:\n  {}\nCommit or discard them before merging.", .0.join("\n  "))]
+    MergeWouldOverwrite(Vec<String>),
+
+    /// The index still has merge conflicts in these paths.
+    #[error("you have unmerged paths:\n  {}\nFix them in the worktree, then `mini-git add` or `mini-git rm` each one.", .0.join("\n  "))]
+    Unmerged(Vec<String>),
+
+    /// `rm` would lose changes stored nowhere else (each entry says which).
+    #[error("rm would lose changes to:\n  {}\nUse --cached to keep the file, or -f to remove it anyway.", .0.join("\n  "))]
+    WouldLoseChanges(Vec<String>),
+
+    /// Repository data (index, commit log, an object) that doesn't parse or verify.
+    #[error("{what} is corrupt: {reason}")]
+    Corrupt { what: String, reason: String },
+
+    /// A request that can't be carried out as asked (e.g., moving a directory into itself).
+    #[error("{0}")]
+    Invalid(String),
+
+    #[error("{context}")]
+    Io {
+        context: String,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("{context}")]
+    Json {
+        context: String,
+        #[source]
+        source: serde_json::Error,
+    },
+}
+
+/// Attach what was being done to an I/O or JSON error, anyhow-style.
+pub(crate) trait Context<T> {
+    fn with_context<C: Into<String>>(self, context: impl FnOnce() -> C) -> Result<T>;
+}
+
+impl<T> Context<T> for std::io::Result<T> {
+    fn with_context<C: Into<String>>(self, context: impl FnOnce() -> C) -> Result<T> {
+        self.map_err(|source| Error::Io { context: context().into(), source })
+    }
+}
+
+impl<T> Context<T> for serde_json::Result<T> {
+    fn with_context<C: Into<String>>(self, context: impl FnOnce() -> C) -> Result<T> {
+        self.map_err(|source| Error::Json { context: context().into(), source })
+    }
+}
@@ -0,0 +1,242 @@
+//! The commit graph in memory: every commit's parents and timestamp, indexed for the
+//! ancestry questions merges and history listings ask (merge bases, reachability, the
+//! `rev-list` orderings). It's built once per [`Repository`] from the commit log, and
+//! rebuilt after the log grows.
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::sync::Arc;
+
+use chrono::{DateTime, FixedOffset};
+
+use crate::commit::Commit;
+use crate::error::{Error, Result};
+use crate::repository::Repository;
+
+/// How [`CommitGraph::rev_list`] orders commits.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RevOrder {
+    /// Newest first, even if a child's clock was behind its parent's.
+    #[default]
+    Chronological,
+    /// Newest first, but never a parent before all of its children.
+    Date,
+    /// Never a parent before all of its children, and each line of history in one run
+    /// (the second parent's side of a merge first).
+    Topo,
+}
+
+/// What [`CommitGraph::rev_list`] lists, beyond "reachable from these, not from those".
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RevListOptions {
+    pub order: RevOrder,
+    /// Only commits descending from an excluded one, i.e. on the ancestry path from the
+    /// excluded commits to the included ones.
+    pub ancestry_path: bool,
+}
+
+/// Every commit's parents and timestamp. Commits are numbered in log order, which puts
+/// parents before children.
+#[derive(Clone, Debug, Default)]
+pub struct CommitGraph {
+    ids: Vec<String>,
+    index: HashMap<String, usize>,
+    parents: Vec<Vec<usize>>,
+    times: Vec<DateTime<FixedOffset>>,
+}
+
+impl CommitGraph {
+    /// The graph of `commits`, given in log order.
+    pub(crate) fn build(commits: &[Commit]) -> Result<Self> {
+        let mut graph = CommitGraph::default();
+        for (i, c) in commits.iter().enumerate() {
+            let when = DateTime::parse_from_rfc3339(&c.timestamp).map_err(|e| Error::Corrupt {
+                what: format!("commit {}", c.id),
+                reason: format!("bad timestamp {}: {e}", c.timestamp),
+            })?;
+            graph.ids.push(c.id.clone());
+            graph.index.insert(c.id.clone(), i);
+            graph.times.push(when);
+        }
+        // a parent missing from the log (a damaged repository) is left out
+        for c in commits {
+            let parents = c.parents().into_iter().filter_map(|p| graph.index.get(p).copied()).collect();
+            graph.parents.push(parents);
+        }
+        Ok(graph)
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    /// Every commit id, in log order.
+    pub fn ids(&self) -> impl Iterator<Item = &str> {
+        self.ids.iter().map(String::as_str)
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.index.contains_key(id)
+    }
+
+    /// Where commit `id` is in the log.
+    pub(crate) fn position(&self, id: &str) -> Option<usize> {
+        self.index.get(id).copied()
+    }
+
+    /// Parent ids of commit `id`, in order (none if it isn't in the graph).
+    pub fn parents(&self, id: &str) -> Vec<&str> {
+        let Some(&i) = self.index.get(id) else { return Vec::new() };
+        self.parents[i].iter().map(|&p| self.ids[p].as_str()).collect()
+    }
+
+    fn indices<'a>(&'a self, ids: &'a [&str]) -> impl Iterator<Item = usize> + 'a {
+        ids.iter().filter_map(|id| self.index.get(*id).copied())
+    }
+
+    /// Which commits are reachable from `starts` (themselves included), by index.
+    fn reachable(&self, starts: impl IntoIterator<Item = usize>) -> Vec<bool> {
+        let mut seen = vec![false; self.len()];
+        let mut stack: Vec<usize> = starts.into_iter().collect();
+        while let Some(i) = stack.pop() {
+            if !seen[i] {
+                seen[i] = true;
+                stack.extend(&self.parents[i]);
+            }
+        }
+        seen
+    }
+
+    /// Is `ancestor` reachable from `descendant` by following parent links? A commit is
+    /// its own ancestor.
+    pub fn is_ancestor(&self, ancestor: &str, descendant: &str) -> bool {
+        let (Some(&a), Some(&d)) = (self.index.get(ancestor), self.index.get(descendant)) else {
+            return ancestor == descendant;
+        };
+        // parents come before children in the log, so nothing below `a` can lead to it
+        let mut seen = vec![false; self.len()];
+        let mut stack = vec![d];
+        while let Some(i) = stack.pop() {
+            if i == a {
+                return true;
+            }
+            if i > a && !seen[i] {
+                seen[i] = true;
+                stack.extend(&self.parents[i]);
+            }
+        }
+        false
+    }
+
+    /// The best of a set of common ancestors: those no other one descends from, newest
+    /// first. The set is closed under "parent of", so a commit that isn't a best one
+    /// always has a child in the set.
+    fn best(&self, common: &[bool]) -> Vec<String> {
+        let mut shadowed = vec![false; self.len()];
+        for i in (0..self.len()).filter(|&i| common[i]) {
+            for &p in &self.parents[i] {
+                shadowed[p] = true;
+            }
+        }
+        let mut best: Vec<usize> = (0..self.len()).filter(|&i| common[i] && !shadowed[i]).collect();
+        best.sort_by_key(|&i| Reverse((self.times[i], i)));
+        best.into_iter().map(|i| self.ids[i].clone()).collect()
+    }
+
+    /// The best common ancestors of `one` and any of `others` (for two commits, their merge
+    /// bases). Usually one; none for unrelated histories.
+    pub fn merge_bases(&self, one: &str, others: &[&str]) -> Vec<String> {
+        let mine = self.reachable(self.indices(&[one]));
+        let theirs = self.reachable(self.indices(others));
+        let common: Vec<bool> = mine.iter().zip(&theirs).map(|(a, b)| *a && *b).collect();
+        self.best(&common)
+    }
+
+    /// The best ancestors common to all of `commits`, as for an octopus merge.
+    pub fn octopus_merge_bases(&self, commits: &[&str]) -> Vec<String> {
+        let mut common = vec![!commits.is_empty(); self.len()];
+        for id in commits {
+            let reach = self.reachable(self.indices(&[id]));
+            common.iter_mut().zip(reach).for_each(|(c, r)| *c &= r);
+        }
+        self.best(&common)
+    }
+
+    /// Commits reachable from `include` but not from `exclude`, in `opts.order`.
+    pub fn rev_list(&self, include: &[&str], exclude: &[&str], opts: RevListOptions) -> Vec<String> {
+        // 1) the set: reachable from an included commit and not from an excluded one
+        let excluded = self.reachable(self.indices(exclude));
+        let mut wanted = self.reachable(self.indices(include));
+        wanted.iter_mut().zip(&excluded).for_each(|(w, x)| *w &= !x);
+
+        // 2) --ancestry-path: keep descendants of the excluded commits (log order visits
+        //    parents first, so each commit's parents are settled before it)
+        if opts.ancestry_path {
+            let mut bottom = vec![false; self.len()];
+            self.indices(exclude).for_each(|i| bottom[i] = true);
+            for i in 0..self.len() {
+                if wanted[i] {
+                    wanted[i] = self.parents[i].iter().any(|&p| bottom[p] || wanted[p]);
+                }
+            }
+        }
+
+        // 3) order them
+        let mut children = vec![0usize; self.len()];
+        for i in (0..self.len()).filter(|&i| wanted[i]) {
+            for &p in self.parents[i].iter().filter(|&&p| wanted[p]) {
+                children[p] += 1;
+            }
+        }
+        let key = |i: usize| (self.times[i], i);
+        let mut ready: Vec<usize> = (0..self.len()).filter(|&i| wanted[i] && children[i] == 0).collect();
+        let mut out = Vec::new();
+        match opts.order {
+            RevOrder::Chronological => {
+                out = (0..self.len()).filter(|&i| wanted[i]).collect();
+                out.sort_by_key(|&i| Reverse(key(i)));
+            }
+            RevOrder::Date => {
+                let mut heap: BinaryHeap<_> = ready.into_iter().map(|i| (key(i), i)).collect();
+                while let Some((_, i)) = heap.pop() {
+                    out.push(i);
+                    for &p in &self.parents[i] {
+                        if wanted[p] {
+                            children[p] -= 1;
+                            if children[p] == 0 {
+                                heap.push((key(p), p));
+                            }
+                        }
+                    }
+                }
+            }
+            RevOrder::Topo => {
+                // a stack: the newest tip first, and each commit's last-pushed parent next
+                ready.sort_by_key(|&i| key(i));
+                while let Some(i) = ready.pop() {
+                    out.push(i);
+                    for &p in &self.parents[i] {
+                        if wanted[p] {
+                            children[p] -= 1;
+                            if children[p] == 0 {
+                                ready.push(p);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        out.into_iter().map(|i| self.ids[i].clone()).collect()
+    }
+}
+
+impl Repository {
+    /// Every commit's parents and timestamp (built on first use, and again after a commit).
+    pub fn commit_graph(&self) -> Result<Arc<CommitGraph>> {
+        Ok(self.history()?.graph.clone())
+    }
+}
@@ -0,0 +1,206 @@
+//! `.minigitignore` rules and the gitignore-style glob matcher behind them (also used
+//! for glob pathspecs).
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::error::{Context, Result};
+
+/// Match `text` against a gitignore-style glob: `*` and `?` stay within one path
+/// segment, `**` spans directories, `[a-z]` / `[!a-z]` are character classes.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    fn class(p: &[u8], c: u8) -> Option<(bool, usize)> {
+        // p starts just after '['; returns (matched, bytes consumed including ']')
+        let mut i = 0;
+        let negate = matches!(p.first(), Some(b'!') | Some(b'^'));
+        if negate {
+            i += 1;
+        }
+        let mut hit = false;
+        let mut first = true;
+        while i < p.len() && (first || p[i] != b']') {
+            first = false;
+            let lo = p[i];
+            if i + 2 < p.len() && p[i + 1] == b'-' && p[i + 2] != b']' {
+                hit |= lo <= c && c <= p[i + 2];
+                i += 3;
+            } else {
+                hit |= lo == c;
+                i += 1;
+            }
+        }
+        (i < p.len()).then_some((hit != negate, i + 1))
+    }
+
+    fn go(p: &[u8], t: &[u8]) -> bool {
+        match p.first() {
+            None => t.is_empty(),
+            Some(b'*') if p.get(1) == Some(&b'*') => {
+                let rest = &p[2..];
+                if let Some(after_slash) = rest.strip_prefix(b"/") {
+                    // "**/" matches zero or more whole directories
+                    (0..=t.len()).any(|i| (i == 0 || t[i - 1] == b'/') && go(after_slash, &t[i..]))
+                } else {
+                    (0..=t.len()).any(|i| go(rest, &t[i..]))
+                }
+            }
+            Some(b'*') => {
+                let seg = t.iter().position(|&c| c == b'/').unwrap_or(t.len());
+                (0..=seg).any(|i| go(&p[1..], &t[i..]))
+            }
+            Some(b'?') => matches!(t.first(), Some(&c) if c != b'/') && go(&p[1..], &t[1..]),
+            Some(b'[') => match (t.first(), class(&p[1..], *t.first().unwrap_or(&0))) {
+                (Some(&c), Some((true, used))) if c != b'/' => go(&p[1 + used..], &t[1..]),
+                (_, Some(_)) => false,
+                // unterminated '[' is a literal
+                (Some(b'['), None) => go(&p[1..], &t[1..]),
+                _ => false,
+            },
+            Some(b'\\') if p.len() > 1 => t.first() == Some(&p[1]) && go(&p[2..], &t[1..]),
+            Some(&c) => t.first() == Some(&c) && go(&p[1..], &t[1..]),
+        }
+    }
+
+    go(pattern.as_bytes(), text.as_bytes())
+}
+
+/// One line of a `.minigitignore` file.
+#[derive(Clone, Debug)]
+pub struct IgnoreRule {
+    /// the line as written, for `check-ignore` output
+    pub text: String,
+    glob: String,
+    /// `!pattern`: re-includes what earlier rules excluded
+    pub negated: bool,
+    dir_only: bool,
+    /// contains a `/` (other than a trailing one): match the full path, not just the name
+    anchored: bool,
+    /// directory holding the ignore file, repo-relative ("" for the root)
+    base: String,
+    /// the ignore file, repo-relative (e.g. "src/.minigitignore")
+    pub source: String,
+    pub line: usize,
+}
+
+impl IgnoreRule {
+    fn parse(raw: &str, base: &str, source: &str, line: usize) -> Option<Self> {
+        let text = raw.trim_end();
+        if text.is_empty() || text.starts_with('#') {
+            return None;
+        }
+        let mut glob = text;
+        let negated = glob.starts_with('!');
+        // a leading backslash escapes a literal `!` or `#`
+        if negated || glob.starts_with("\\!") || glob.starts_with("\\#") {
+            glob = &glob[1..];
+        }
+        let dir_only = glob.ends_with('/');
+        let glob = glob.trim_end_matches('/');
+        let anchored = glob.contains('/');
+        let glob = glob.trim_start_matches('/');
+        if glob.is_empty() {
+            return None;
+        }
+        Some(IgnoreRule {
+            text: text.to_string(),
+            glob: glob.to_string(),
+            negated,
+            dir_only,
+            anchored,
+            base: base.to_string(),
+            source: source.to_string(),
+            line,
+        })
+    }
+
+    fn matches(&self, rel: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let local = if self.base.is_empty() {
+            rel
+        } else {
+            match rel.strip_prefix(&self.base).and_then(|r| r.strip_prefix('/')) {
+                Some(r) => r,
+                None => return false,
+            }
+        };
+        if self.anchored {
+            glob_match(&self.glob, local)
+        } else {
+            let name = local.rsplit('/').next().unwrap_or(local);
+            glob_match(&self.glob, name)
+        }
+    }
+}
+
+/// `.minigitignore` rules from the worktree root and every subdirectory, loaded lazily.
+/// Deeper files are consulted after shallower ones, and the last matching rule wins.
+pub struct Ignore {
+    worktree: PathBuf,
+    by_dir: HashMap<String, Vec<IgnoreRule>>,
+}
+
+impl Ignore {
+    /// Rules for the worktree rooted at `worktree`.
+    pub fn new(worktree: impl Into<PathBuf>) -> Self {
+        Ignore { worktree: worktree.into(), by_dir: HashMap::new() }
+    }
+
+    /// Rules from `<dir>/.minigitignore` (`dir` repo-relative, "" for the root).
+    fn rules_in(&mut self, dir: &str) -> Result<&[IgnoreRule]> {
+        if !self.by_dir.contains_key(dir) {
+            let file = self.worktree.join(dir).join(".minigitignore");
+            let source = if dir.is_empty() { ".minigitignore".to_string() } else { format!("{dir}/.minigitignore") };
+            let rules = match fs::read_to_string(&file) {
+                Ok(text) => text
+                    .lines()
+                    .enumerate()
+                    .filter_map(|(i, l)| IgnoreRule::parse(l, dir, &source, i + 1))
+                    .collect(),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+                Err(e) => return Err(e).with_context(|| format!("reading {}", file.display())),
+            };
+            self.by_dir.insert(dir.to_string(), rules);
+        }
+        Ok(&self.by_dir[dir])
+    }
+
+    /// Last rule matching `rel` itself, ignoring whether a parent directory is excluded.
+    pub fn rule_for(&mut self, rel: &str, is_dir: bool) -> Result<Option<IgnoreRule>> {
+        let mut dirs = vec![String::new()];
+        let parts: Vec<&str> = rel.split('/').collect();
+        for i in 1..parts.len() {
+            dirs.push(parts[..i].join("/"));
+        }
+        let mut found = None;
+        for d in dirs {
+            if let Some(r) = self.rules_in(&d)?.iter().rev().find(|r| r.matches(rel, is_dir)) {
+                found = Some(r.clone());
+            }
+        }
+        Ok(found)
+    }
+
+    /// The rule deciding `rel`'s fate, including an excluded parent directory
+    /// (files inside an ignored directory can't be re-included, as in git).
+    pub fn explain(&mut self, rel: &str, is_dir: bool) -> Result<Option<IgnoreRule>> {
+        let parts: Vec<&str> = rel.split('/').collect();
+        for i in 1..parts.len() {
+            if let Some(rule) = self.rule_for(&parts[..i].join("/"), true)?
+                && !rule.negated
+            {
+                return Ok(Some(rule));
+            }
+        }
+        self.rule_for(rel, is_dir)
+    }
+
+    pub fn is_ignored(&mut self, rel: &str, is_dir: bool) -> Result<bool> {
+        if rel == ".minigit" || rel.starts_with(".minigit/") {
+            return Ok(true);
+        }
+        Ok(self.explain(rel, is_dir)?.is_some_and(|r| !r.negated))
+    }
+}
@@ -0,0 +1,412 @@
+//! The staging area: path -> mode + blob id + cached stat data (plus the stages of any
+//! unresolved merge conflicts), stored in `.minigit/index.bin`, and the snapshot types
+//! shared by the index, commits and the working tree.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+
+use crate::error::{Context, Error, Result};
+use crate::objects::{hex_to_bytes, to_hex};
+
+/// Stat data cached per index entry, so unchanged files needn't be re-read and re-hashed.
+/// All zeros means "unknown" (e.g., migrated from index.json): always re-hash.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FileStat {
+    pub mtime: i64,
+    pub mtime_nsec: u32,
+    pub ctime: i64,
+    pub ctime_nsec: u32,
+    pub dev: u64,
+    pub ino: u64,
+    pub st_mode: u32,
+    pub size: u64,
+}
+
+impl FileStat {
+    #[cfg(unix)]
+    pub fn from_metadata(meta: &fs::Metadata) -> Self {
+        use std::os::unix::fs::MetadataExt;
+        FileStat {
+            mtime: meta.mtime(),
+            mtime_nsec: meta.mtime_nsec() as u32,
+            ctime: meta.ctime(),
+            ctime_nsec: meta.ctime_nsec() as u32,
+            dev: meta.dev(),
+            ino: meta.ino(),
+            st_mode: meta.mode(),
+            size: meta.size(),
+        }
+    }
+
+    #[cfg(not(unix))]
+    pub fn from_metadata(meta: &fs::Metadata) -> Self {
+        let since_epoch = |t: std::io::Result<std::time::SystemTime>| {
+            t.ok().and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok()).unwrap_or_default()
+        };
+        let m = since_epoch(meta.modified());
+        let c = since_epoch(meta.created());
+        FileStat {
+            mtime: m.as_secs() as i64,
+            mtime_nsec: m.subsec_nanos(),
+            ctime: c.as_secs() as i64,
+            ctime_nsec: c.subsec_nanos(),
+            size: meta.len(),
+            ..FileStat::default()
+        }
+    }
+}
+
+/* File modes, as git records them: a regular file, an executable, or a symlink
+ * (whose blob holds the link target). */
+pub const MODE_FILE: u32 = 0o100644;
+pub const MODE_EXEC: u32 = 0o100755;
+pub const MODE_SYMLINK: u32 = 0o120000;
+
+/// The mode to record for a path, from `symlink_metadata` (so links aren't followed).
+pub fn mode_of(meta: &fs::Metadata) -> u32 {
+    if meta.file_type().is_symlink() {
+        return MODE_SYMLINK;
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if meta.permissions().mode() & 0o111 != 0 {
+            return MODE_EXEC;
+        }
+    }
+    MODE_FILE
+}
+
+/// What a snapshot records per path: mode and content.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileEntry {
+    pub mode: u32,
+    pub blob: String,
+}
+
+/// path -> (mode, blob): the common shape of the index, a commit and the working tree.
+pub type Snapshot = BTreeMap<String, FileEntry>;
+
+/// One staged path: its mode and blob plus the stat data seen when it was hashed.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct IndexEntry {
+    pub blob: String,
+    pub mode: u32,
+    #[serde(flatten)]
+    pub stat: FileStat,
+}
+
+impl IndexEntry {
+    /// Is `blob` still right for a file with this metadata, without reading it?
+    pub fn matches_stat(&self, meta: &fs::Metadata) -> bool {
+        self.stat != FileStat::default() && self.stat == FileStat::from_metadata(meta)
+    }
+}
+
+/// The versions of a path a merge couldn't reconcile: git's index stages 1 (base),
+/// 2 (ours) and 3 (theirs). `None` where that side has no such file.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Conflict {
+    pub base: Option<FileEntry>,
+    pub ours: Option<FileEntry>,
+    pub theirs: Option<FileEntry>,
+}
+
+impl Conflict {
+    /// The stages in order, numbered like git's.
+    pub fn stages(&self) -> impl Iterator<Item = (u8, &FileEntry)> {
+        [(1, &self.base), (2, &self.ours), (3, &self.theirs)].into_iter().filter_map(|(n, e)| Some((n, e.as_ref()?)))
+    }
+
+    /// `git status --porcelain`'s two-letter code: UU, AA, UD or DU.
+    pub fn code(&self) -> &'static str {
+        match (&self.base, &self.ours, &self.theirs) {
+            (None, _, _) => "AA",
+            (_, Some(_), None) => "UD",
+            (_, None, Some(_)) => "DU",
+            _ => "UU",
+        }
+    }
+
+    /// How `status` describes it.
+    pub fn describe(&self) -> &'static str {
+        match self.code() {
+            "AA" => "both added",
+            "UD" => "deleted by them",
+            "DU" => "deleted by us",
+            _ => "both modified",
+        }
+    }
+}
+
+/// path -> entry (e.g., "src/main.rs" -> blob "a94a8fe5..." + stat data), sorted by path.
+/// Derefs to the underlying map for lookups and edits.
+///
+/// While a merge has unresolved `conflicts`, a conflicted path keeps our version (if any)
+/// as its entry; staging or removing the path resolves the conflict.
+#[derive(Serialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct Index {
+    entries: BTreeMap<String, IndexEntry>,
+    #[serde(skip)]
+    pub conflicts: BTreeMap<String, Conflict>,
+}
+
+impl Deref for Index {
+    type Target = BTreeMap<String, IndexEntry>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.entries
+    }
+}
+
+impl DerefMut for Index {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.entries
+    }
+}
+
+impl<'a> IntoIterator for &'a Index {
+    type Item = (&'a String, &'a IndexEntry);
+    type IntoIter = std::collections::btree_map::Iter<'a, String, IndexEntry>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.iter()
+    }
+}
+
+impl Index {
+    pub fn new() -> Self {
+        Index::default()
+    }
+
+    /// Just the path -> (mode, blob) part (what a commit records).
+    pub fn snapshot(&self) -> Snapshot {
+        self.iter().map(|(p, e)| (p.clone(), FileEntry { mode: e.mode, blob: e.blob.clone() })).collect()
+    }
+
+    /// Tracked paths named by `rel`: the path itself or everything under it as a directory
+    /// ("" is the whole worktree).
+    pub fn tracked_under<'a>(&'a self, rel: &'a str) -> impl Iterator<Item = &'a String> {
+        self.keys().filter(move |p| is_under(p, rel))
+    }
+
+    /// Paths with merge conflicts at or under `rel`, like [`Index::tracked_under`].
+    pub fn unmerged_under<'a>(&'a self, rel: &'a str) -> impl Iterator<Item = &'a String> {
+        self.conflicts.keys().filter(move |p| is_under(p, rel))
+    }
+
+    /* Binary index layout (integers big-endian):
+     *   "MGIX" | version u32 | entry count u32
+     *   per entry: mtime i64 | mtime_nsec u32 | ctime i64 | ctime_nsec u32 | dev u64 | ino u64
+     *              | st_mode u32 | size u64 | mode u32 (v2+) | id length u8 | raw id
+     *              | path length u16 | path (UTF-8)
+     *   conflict count u32 (v3+)
+     *   per conflict: path length u16 | path | for base, ours, theirs:
+     *              present u8, then if present: mode u32 | id length u8 | raw id
+     *   SHA-1 of everything above (integrity check)
+     * Version 1 had no recorded mode; its entries load as regular files.
+     */
+    const MAGIC: &[u8; 4] = b"MGIX";
+    const VERSION: u32 = 3;
+
+    /// Serialize in the binary format above.
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        // Racy-git guard: a file modified within the same second the index is written could
+        // change again without its mtime changing, so don't trust its stat next time.
+        let now = chrono::Utc::now().timestamp();
+
+        let mut out = Vec::new();
+        out.extend_from_slice(Self::MAGIC);
+        out.extend_from_slice(&Self::VERSION.to_be_bytes());
+        out.extend_from_slice(&(self.len() as u32).to_be_bytes());
+        for (path, e) in self {
+            let s = if e.stat.mtime >= now { FileStat::default() } else { e.stat };
+            out.extend_from_slice(&s.mtime.to_be_bytes());
+            out.extend_from_slice(&s.mtime_nsec.to_be_bytes());
+            out.extend_from_slice(&s.ctime.to_be_bytes());
+            out.extend_from_slice(&s.ctime_nsec.to_be_bytes());
+            out.extend_from_slice(&s.dev.to_be_bytes());
+            out.extend_from_slice(&s.ino.to_be_bytes());
+            out.extend_from_slice(&s.st_mode.to_be_bytes());
+            out.extend_from_slice(&s.size.to_be_bytes());
+            out.extend_from_slice(&e.mode.to_be_bytes());
+            let id = hex_to_bytes(&e.blob)?;
+            out.push(id.len() as u8);
+            out.extend_from_slice(&id);
+            push_path(&mut out, path)?;
+        }
+        out.extend_from_slice(&(self.conflicts.len() as u32).to_be_bytes());
+        for (path, conflict) in &self.conflicts {
+            push_path(&mut out, path)?;
+            for stage in [&conflict.base, &conflict.ours, &conflict.theirs] {
+                let Some(e) = stage else {
+                    out.push(0);
+                    continue;
+                };
+                out.push(1);
+                out.extend_from_slice(&e.mode.to_be_bytes());
+                let id = hex_to_bytes(&e.blob)?;
+                out.push(id.len() as u8);
+                out.extend_from_slice(&id);
+            }
+        }
+        let sum = Sha1::digest(&out);
+        out.extend_from_slice(&sum);
+        Ok(out)
+    }
+
+    /// Parse the binary format; the error is the reason the bytes aren't a valid index.
+    pub fn decode(bytes: &[u8]) -> Result<Self, String> {
+        // Tiny cursor over the buffer; every read is bounds-checked.
+        struct Reader<'a>(&'a [u8]);
+        impl<'a> Reader<'a> {
+            fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+                if self.0.len() < n {
+                    return Err("index is truncated".to_string());
+                }
+                let (head, rest) = self.0.split_at(n);
+                self.0 = rest;
+                Ok(head)
+            }
+            fn array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+                Ok(self.take(N)?.try_into().unwrap_or([0; N]))
+            }
+            fn path(&mut self) -> Result<String, String> {
+                let len = u16::from_be_bytes(self.array()?) as usize;
+                let path = std::str::from_utf8(self.take(len)?).map_err(|_| "index path is not UTF-8".to_string())?;
+                Ok(path.to_string())
+            }
+        }
+
+        // 1) trailer checksum, then header
+        if bytes.len() < 20 {
+            return Err("index is truncated".to_string());
+        }
+        let (body, sum) = bytes.split_at(bytes.len() - 20);
+        if Sha1::digest(body).as_slice() != sum {
+            return Err("index checksum mismatch".to_string());
+        }
+        let mut r = Reader(body);
+        if r.take(4)? != Self::MAGIC {
+            return Err("not a mini-git index (bad magic)".to_string());
+        }
+        let version = u32::from_be_bytes(r.array()?);
+        if !(1..=Self::VERSION).contains(&version) {
+            return Err(format!("unsupported index version {version}"));
+        }
+
+        // 2) entries
+        let count = u32::from_be_bytes(r.array()?);
+        let mut index = Index::new();
+        for _ in 0..count {
+            let stat = FileStat {
+                mtime: i64::from_be_bytes(r.array()?),
+                mtime_nsec: u32::from_be_bytes(r.array()?),
+                ctime: i64::from_be_bytes(r.array()?),
+                ctime_nsec: u32::from_be_bytes(r.array()?),
+                dev: u64::from_be_bytes(r.array()?),
+                ino: u64::from_be_bytes(r.array()?),
+                st_mode: u32::from_be_bytes(r.array()?),
+                size: u64::from_be_bytes(r.array()?),
+            };
+            let mode = if version >= 2 { u32::from_be_bytes(r.array()?) } else { MODE_FILE };
+            let id_len = r.take(1)?[0] as usize;
+            let blob = to_hex(r.take(id_len)?);
+            let path = r.path()?;
+            index.insert(path, IndexEntry { blob, mode, stat });
+        }
+
+        // 3) conflict stages
+        let count = if version >= 3 { u32::from_be_bytes(r.array()?) } else { 0 };
+        for _ in 0..count {
+            let path = r.path()?;
+            let mut stages = [None, None, None];
+            for stage in &mut stages {
+                if r.take(1)?[0] == 0 {
+                    continue;
+                }
+                let mode = u32::from_be_bytes(r.array()?);
+                let id_len = r.take(1)?[0] as usize;
+                *stage = Some(FileEntry { mode, blob: to_hex(r.take(id_len)?) });
+            }
+            let [base, ours, theirs] = stages;
+            index.conflicts.insert(path, Conflict { base, ours, theirs });
+        }
+        Ok(index)
+    }
+
+    /// Pre-binary index: a JSON map of path -> blob id, with no stat data or modes.
+    pub(crate) fn from_legacy_json(bytes: &[u8]) -> Result<Self> {
+        let blobs: BTreeMap<String, String> = serde_json::from_slice(bytes).with_context(|| "parsing index.json as JSON")?;
+        let entries = blobs
+            .into_iter()
+            .map(|(p, blob)| (p, IndexEntry { blob, mode: MODE_FILE, stat: FileStat::default() }))
+            .collect();
+        Ok(Index { entries, conflicts: BTreeMap::new() })
+    }
+}
+
+/// Is `path` the repo-relative path `rel`, or inside it ("" is the whole worktree)?
+pub(crate) fn is_under(path: &str, rel: &str) -> bool {
+    rel.is_empty() || path == rel || path.strip_prefix(rel).is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// `path length u16 | path`, as the binary index stores paths.
+fn push_path(out: &mut Vec<u8>, path: &str) -> Result<()> {
+    let len = u16::try_from(path.len()).map_err(|_| Error::Invalid(format!("path too long for the index: {path}")))?;
+    out.extend_from_slice(&len.to_be_bytes());
+    out.extend_from_slice(path.as_bytes());
+    Ok(())
+}
+
+/// Exclusive right to rewrite the index, held as `.minigit/index.lock` (created with
+/// O_EXCL, like git). Take it *before* loading the index so two concurrent runs can't
+/// lose each other's updates. Dropping it without saving releases the lock.
+#[derive(Debug)]
+pub struct IndexLock {
+    pub(crate) file: fs::File,
+    pub(crate) path: PathBuf,
+    pub(crate) saved: bool,
+}
+
+impl IndexLock {
+    /// How long to wait for another mini-git process to release the lock.
+    const TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+    /// Take the lock only if it's free right now (for optional work like refreshing stat data).
+    pub(crate) fn try_acquire(path: &Path) -> Result<Option<Self>> {
+        match fs::OpenOptions::new().write(true).create_new(true).open(path) {
+            Ok(file) => Ok(Some(IndexLock { file, path: path.to_path_buf(), saved: false })),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("creating {}", path.display())),
+        }
+    }
+
+    pub(crate) fn acquire(path: &Path) -> Result<Self> {
+        let start = std::time::Instant::now();
+        loop {
+            match fs::OpenOptions::new().write(true).create_new(true).open(path) {
+                Ok(file) => return Ok(IndexLock { file, path: path.to_path_buf(), saved: false }),
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists && start.elapsed() < Self::TIMEOUT => {
+                    std::thread::sleep(std::time::Duration::from_millis(50));
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => return Err(Error::Locked(path.to_path_buf())),
+                Err(e) => return Err(e).with_context(|| format!("creating {}", path.display())),
+            }
+        }
+    }
+}
+
+impl Drop for IndexLock {
+    fn drop(&mut self) {
+        if !self.saved {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
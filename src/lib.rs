@@ -0,0 +1,54 @@
+//! mini-git as a library: the repository format, object store, index and the porcelain
+//! operations behind the `mini-git` command, for tools that want to embed them.
+//!
+//! ```no_run
+//! use mini_git::{AddOptions, Repository};
+//! use std::path::Path;
+//!
+//! # fn main() -> mini_git::Result<()> {
+//! let repo = Repository::discover(Path::new("/path/to/worktree"))?;
+//! repo.add(&["src".into()], AddOptions::default())?;
+//! let commit = repo.commit("Update sources".to_string())?;
+//! println!("{}", commit.id);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Nothing here prints or reads the process's current directory: relative paths are
+//! resolved against the directory a [`Repository`] was discovered from (or
+//! [`Repository::with_cwd`]), and results come back as values for the caller to show.
+
+pub mod commit;
+pub mod diff;
+mod error;
+pub mod graph;
+pub mod ignore;
+pub mod index;
+mod merge;
+pub mod objects;
+mod patch;
+pub mod refs;
+mod repository;
+pub mod rev;
+mod stage;
+pub mod tag;
+#[cfg(test)]
+mod testing;
+mod tree_diff;
+mod util;
+mod worktree;
+
+pub use commit::Commit;
+pub use error::{Error, Result};
+pub use graph::{CommitGraph, RevListOptions, RevOrder};
+pub use index::{Conflict, FileEntry, Index, IndexEntry, IndexLock, Snapshot};
+pub use merge::{Merge, MergeOptions};
+pub use objects::{ObjectFormat, ObjectStore};
+pub use patch::{FilePatch, PatchLine};
+pub use refs::{Head, ReflogEntry};
+pub use repository::{Config, FsckReport, Repository};
+pub use rev::Revision;
+pub use stage::{is_pathspec_glob, pathspec_matches, AddOptions, AddReport, Move, Staged};
+pub use tag::Tag;
+pub use tree_diff::{DiffSide, FileDiff};
+pub use worktree::{read_worktree_file, Checkout, Status};
@@ -1,191 +1,1434 @@
 use anyhow::{bail, Context, Result};
 use std::{fs, path::{Path, PathBuf}};
-use std::collections::HashMap;   // in-memory key/value store
-use sha1::{Digest, Sha1};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::IsTerminal;
+use std::process::ExitCode;
+use std::sync::OnceLock;
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use mini_git::diff;
+use mini_git::ignore::{glob_match, Ignore};
+use mini_git::is_pathspec_glob;
+use mini_git::{Checkout, Commit, Config, DiffSide, FileDiff, FileEntry, FilePatch, Head, Merge, ObjectFormat, Repository, RevOrder, Revision, Staged, Status};
 
-/* -------- repo paths -------- */
+/* -------- output -------- */
 
-fn repo_dir() -> PathBuf {
-    PathBuf::from(".minigit")
+/// How chatty informational messages are (set once from the global flags).
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+enum Verbosity {
+    Quiet,
+    Normal,
+    Verbose,
 }
 
-fn objects_dir() -> PathBuf {
-    repo_dir().join("objects")
+static VERBOSITY: OnceLock<Verbosity> = OnceLock::new();
+static USE_COLOR: OnceLock<bool> = OnceLock::new();
+
+fn verbosity() -> Verbosity {
+    *VERBOSITY.get().unwrap_or(&Verbosity::Normal)
+}
+
+/// Informational message: suppressed by `--quiet`.
+macro_rules! say {
+    ($($arg:tt)*) => {
+        if verbosity() >= Verbosity::Normal {
+            println!($($arg)*);
+        }
+    };
 }
 
-fn index_path() -> PathBuf {
-    repo_dir().join("index.json")
+/// Extra detail: only shown with `--verbose`.
+macro_rules! detail {
+    ($($arg:tt)*) => {
+        if verbosity() >= Verbosity::Verbose {
+            println!($($arg)*);
+        }
+    };
+}
+
+/// Wrap `text` in an ANSI color code when color output is enabled.
+fn paint(text: &str, ansi: &str) -> String {
+    if *USE_COLOR.get().unwrap_or(&false) {
+        format!("\x1b[{ansi}m{text}\x1b[0m")
+    } else {
+        text.to_string()
+    }
 }
 
-fn commits_path() -> PathBuf {
-    repo_dir().join("commits.jsonl")
+const GREEN: &str = "32";
+const RED: &str = "31";
+const YELLOW: &str = "33";
+const CYAN: &str = "36";
+const BOLD: &str = "1";
+
+/* -------- repository -------- */
+
+/// An explicitly chosen repository directory: `--git-dir` wins over `$MINIGIT_DIR`.
+fn explicit_git_dir(cwd: &Path, flag: Option<&Path>) -> Option<PathBuf> {
+    if let Some(dir) = flag {
+        return Some(cwd.join(dir));
+    }
+    match std::env::var_os("MINIGIT_DIR") {
+        Some(dir) if !dir.is_empty() => Some(cwd.join(dir)),
+        _ => None,
+    }
 }
 
-/* -------- guard -------- */
+/// Find the repository: an explicit `--git-dir`/`$MINIGIT_DIR` (worktree = `cwd`),
+/// otherwise the nearest `.minigit/` in `cwd` or any parent.
+fn open_repo(cwd: &Path, git_dir: Option<&Path>) -> Result<Repository> {
+    Ok(match git_dir {
+        Some(dir) => Repository::open(dir, cwd)?,
+        None => Repository::discover(cwd)?,
+    })
+}
 
-/// Ensure we're inside a mini-git repo (i.e., `.minigit/` exists).
-fn ensure_repo() -> Result<()> {
-    if !repo_dir().exists() {
-        bail!("Not a mini-git repo (missing .minigit). Run `mini-git init` first.");
+fn cmd_init(cwd: &Path, git_dir: Option<PathBuf>, object_format: ObjectFormat, git_compatible: bool) -> Result<()> {
+    // messages name the directory as the user would: `.minigit` unless one was given
+    let shown = git_dir.clone().unwrap_or_else(|| PathBuf::from(".minigit"));
+    let git_dir = git_dir.unwrap_or_else(|| cwd.join(".minigit"));
+    match Repository::init(&git_dir, cwd, Config { object_format, git_compatible }) {
+        Ok(_) => say!("Initialized empty mini-git repo ({}) in {}/", object_format.name(), shown.display()),
+        Err(mini_git::Error::AlreadyInitialized(_)) => say!("{} already exists", shown.display()),
+        Err(e) => return Err(e.into()),
     }
     Ok(())
 }
 
-/// path -> blob_id (e.g., "src/main.rs" -> "a94a8fe5...").
-type Index = HashMap<String, String>;
+/// Flags accepted by `mini-git add`.
+#[derive(clap::Args, Default)]
+struct AddOptions {
+    /// Files, directories or globs like 'src/**/*.rs' (default with -A/-u/-p: the whole worktree)
+    #[arg(value_name = "pathspec", required_unless_present_any = ["all", "update", "patch"])]
+    paths: Vec<PathBuf>,
+    /// Also add paths excluded by .minigitignore when named explicitly
+    #[arg(short, long)]
+    force: bool,
+    /// Stage additions, modifications and deletions everywhere in the pathspec
+    #[arg(short = 'A', long, conflicts_with = "update")]
+    all: bool,
+    /// Only stage modifications and deletions of already-tracked files
+    #[arg(short, long)]
+    update: bool,
+    /// Show what would be staged without touching the index or object store
+    #[arg(short = 'n', long)]
+    dry_run: bool,
+    /// Interactively pick which hunks of tracked files to stage
+    #[arg(short, long, conflicts_with_all = ["all", "update", "dry_run", "force"])]
+    patch: bool,
+}
 
-/// Load `.minigit/index.json`. If it doesn't exist, return an empty map.
-/// Returns Result<Index> so errors bubble up cleanly.
-fn load_index() -> Result<Index> {
-    if !index_path().exists() {
-        return Ok(Index::new());
+fn cmd_add(repo: &Repository, opts: AddOptions) -> Result<()> {
+    if opts.patch {
+        return add_patch(repo, &opts.paths);
     }
-    // Read the whole file into bytes
-    let bytes = fs::read(index_path())
-        .with_context(|| format!("reading {}", index_path().display()))?;
 
-    // Turn JSON bytes into an Index (HashMap<String, String>)
-    let idx: Index = serde_json::from_slice(&bytes)
-        .with_context(|| "parsing index.json as JSON")?;
+    // 1) stage what the pathspecs name, deletions included
+    let flags = mini_git::AddOptions { force: opts.force, update: opts.update, dry_run: opts.dry_run };
+    let report = repo.add(&opts.paths, flags)?;
+    for p in &report.skipped {
+        eprintln!("Skipping (ignored, use --force to add anyway): {}", p.display());
+    }
 
-    Ok(idx)
+    // 2) report each path (all of them on a dry run, which is all it does)
+    let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+    for (rel, change) in &report.changes {
+        let (verb, tally) = match change {
+            Staged::Added => ("add", "added"),
+            Staged::Modified => ("add", "modified"),
+            Staged::Removed => ("remove", "removed"),
+        };
+        if opts.dry_run {
+            say!("{verb} '{rel}'");
+        } else {
+            detail!("{verb} '{rel}'");
+        }
+        *counts.entry(tally).or_default() += 1;
+    }
+    if opts.dry_run {
+        return Ok(());
+    }
+    let total: usize = counts.values().sum();
+    if total == 0 {
+        say!("Nothing to stage.");
+    } else {
+        let parts: Vec<String> = ["added", "modified", "removed"]
+            .iter()
+            .filter_map(|k| counts.get(k).map(|n| format!("{n} {k}")))
+            .collect();
+        say!("Staged {total} path(s): {}.", parts.join(", "));
+    }
+    Ok(())
 }
 
-/// Save the Index as human-readable JSON (pretty) to `.minigit/index.json`.
-fn save_index(index: &Index) -> Result<()> {
-    // Serialize the HashMap as JSON bytes
-    let data = serde_json::to_vec_pretty(index)
-        .with_context(|| "serializing index to JSON")?;
+fn cmd_commit(repo: &Repository, message: String) -> Result<()> {
+    let commit = repo.commit(message)?;
+    say!("[{}] {}", &commit.id[..7], commit.subject());
+    Ok(())
+}
+
+/// Flags accepted by `mini-git log`.
+#[derive(clap::Args, Default)]
+struct LogOptions {
+    /// One line per commit: short id and subject
+    #[arg(long)]
+    oneline: bool,
+    /// Show at most <count> commits
+    #[arg(short = 'n', long, value_name = "count")]
+    max_count: Option<usize>,
+    /// Only commits at or after <date> (YYYY-MM-DD or RFC 3339)
+    #[arg(long, value_name = "date", value_parser = parse_date)]
+    since: Option<chrono::DateTime<chrono::FixedOffset>>,
+    /// Only commits at or before <date> (YYYY-MM-DD or RFC 3339)
+    #[arg(long, value_name = "date", value_parser = parse_date)]
+    until: Option<chrono::DateTime<chrono::FixedOffset>>,
+    /// Only commits that changed this file or directory
+    #[arg(long, value_name = "file")]
+    path: Option<String>,
+}
+
+/// Accept either a full RFC 3339 timestamp or a plain `YYYY-MM-DD` (local midnight).
+fn parse_date(s: &str) -> Result<chrono::DateTime<chrono::FixedOffset>> {
+    if let Ok(t) = chrono::DateTime::parse_from_rfc3339(s) {
+        return Ok(t);
+    }
+    let day = chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .with_context(|| format!("invalid date {s:?} (expected YYYY-MM-DD or RFC 3339)"))?;
+    let local = day
+        .and_hms_opt(0, 0, 0)
+        .and_then(|t| t.and_local_timezone(chrono::Local).earliest())
+        .with_context(|| format!("invalid local date {s:?}"))?;
+    Ok(local.fixed_offset())
+}
+
+fn cmd_log(repo: &Repository, opts: LogOptions) -> Result<()> {
+    let commits = repo.commits()?;
+    let by_id: HashMap<&str, &Commit> = commits.iter().map(|c| (c.id.as_str(), c)).collect();
+
+    // Everything reachable from HEAD (through every parent of a merge), newest first.
+    let head = repo.head()?;
+    let tips: Vec<&str> = head.as_deref().into_iter().collect();
+    let history = repo.commit_graph()?.rev_list(&tips, &[], mini_git::RevListOptions::default());
 
-    // Write to disk
-    fs::write(index_path(), data)
-        .with_context(|| format!("writing {}", index_path().display()))?;
+    let mut shown = 0;
+    for id in history {
+        if opts.max_count.is_some_and(|n| shown >= n) {
+            break;
+        }
+        let commit = *by_id.get(id.as_str()).with_context(|| format!("HEAD history references missing commit {id}"))?;
+        let when = chrono::DateTime::parse_from_rfc3339(&commit.timestamp)
+            .with_context(|| format!("bad timestamp in commit {}", commit.id))?;
+        let parent = commit.parent.as_deref().and_then(|p| by_id.get(p).copied());
+        if opts.since.is_some_and(|t| when < t) || opts.until.is_some_and(|t| when > t) {
+            continue;
+        }
+        if let Some(path) = &opts.path
+            && !commit.touches_path(parent, path.trim_end_matches('/'))
+        {
+            continue;
+        }
 
+        if opts.oneline {
+            println!("{} {}", paint(&commit.id[..7], YELLOW), commit.message.lines().next().unwrap_or(""));
+        } else {
+            if shown > 0 {
+                println!();
+            }
+            println!("{}", paint(&format!("commit {}", commit.id), YELLOW));
+            if !commit.merge_parents.is_empty() {
+                let short: Vec<&str> = commit.parents().iter().map(|p| &p[..7]).collect();
+                println!("Merge: {}", short.join(" "));
+            }
+            println!("Author: {}", commit.author);
+            println!("Date:   {}", when.format("%a %b %e %H:%M:%S %Y %z"));
+            println!();
+            for line in commit.message.lines() {
+                println!("    {line}");
+            }
+        }
+        shown += 1;
+    }
     Ok(())
 }
 
+/* -------- status -------- */
 
+/// `XY path` lines like `git status --porcelain`: X = index vs HEAD, Y = worktree vs index.
+fn print_porcelain(st: &Status) {
+    let mut lines: BTreeMap<&str, [char; 2]> = BTreeMap::new();
+    let marks = [
+        (&st.staged_new, 0, 'A'),
+        (&st.staged_modified, 0, 'M'),
+        (&st.staged_deleted, 0, 'D'),
+        (&st.unstaged_modified, 1, 'M'),
+        (&st.deleted, 1, 'D'),
+    ];
+    for (paths, slot, c) in marks {
+        for p in paths {
+            lines.entry(p.as_str()).or_insert([' ', ' '])[slot] = c;
+        }
+    }
+    for (path, c) in &st.unmerged {
+        let code: Vec<char> = c.code().chars().collect();
+        lines.insert(path, [code[0], code[1]]);
+    }
+    for (path, [x, y]) in lines {
+        println!("{x}{y} {path}");
+    }
+    for p in &st.untracked {
+        println!("?? {p}");
+    }
+}
 
-// Turn any input bytes into a lowercase SHA-1 hex string (40 chars).
-fn sha1_hex(bytes: impl AsRef<[u8]>) -> String {
-    // 1) Make a new SHA-1 hasher.
-    let mut h = Sha1::new();
+fn print_long_status(st: &Status) {
+    if st.is_clean() {
+        println!("nothing to commit, working tree clean");
+        return;
+    }
+    let section = |title: &str, color: &str, groups: &[(&str, &Vec<String>)]| {
+        if groups.iter().all(|(_, paths)| paths.is_empty()) {
+            return;
+        }
+        println!("{title}:");
+        for (label, paths) in groups {
+            for p in *paths {
+                if label.is_empty() {
+                    println!("        {}", paint(p, color));
+                } else {
+                    println!("        {}", paint(&format!("{label:<12}{p}"), color));
+                }
+            }
+        }
+        println!();
+    };
+    if !st.unmerged.is_empty() {
+        println!("Unmerged paths:");
+        for (path, c) in &st.unmerged {
+            println!("        {}", paint(&format!("{:<18}{path}", format!("{}:", c.describe())), RED));
+        }
+        println!();
+    }
+    section("Changes to be committed", GREEN, &[
+        ("new file:", &st.staged_new),
+        ("modified:", &st.staged_modified),
+        ("deleted:", &st.staged_deleted),
+    ]);
+    section("Changes not staged for commit", RED, &[("modified:", &st.unstaged_modified), ("deleted:", &st.deleted)]);
+    section("Untracked files", RED, &[("", &st.untracked)]);
+}
 
-    // 2) Feed the input (as bytes) into the hasher. (No copies; as_ref() borrows.)
-    h.update(bytes.as_ref());
+fn cmd_status(repo: &Repository, porcelain: bool) -> Result<()> {
+    let st = repo.status()?;
+    if porcelain {
+        print_porcelain(&st);
+    } else {
+        match repo.head_ref()? {
+            Head::Branch(name) => println!("On branch {name}"),
+            Head::Detached(id) => println!("{}", paint(&format!("HEAD detached at {}", &id[..7]), RED)),
+        }
+        if repo.head()?.is_none() {
+            println!("\nNo commits yet\n");
+        }
+        if repo.merge_head()?.is_some() {
+            if st.unmerged.is_empty() {
+                println!("All conflicts fixed but you are still merging.");
+                println!("  (use \"mini-git merge --continue\" to conclude merge)\n");
+            } else {
+                println!("You have unmerged paths.");
+                println!("  (fix conflicts, `add` them, and run \"mini-git merge --continue\")");
+                println!("  (use \"mini-git merge --abort\" to abort the merge)\n");
+            }
+        }
+        print_long_status(&st);
+    }
+    Ok(())
+}
 
-    // 3) Finish the hash: get 20 raw bytes (not text!).
-    let out = h.finalize(); // e.g. [0xaa, 0xf4, 0xc6, …] for "hello"
+/* -------- checkout / switch -------- */
 
-    // 4) A tiny lookup table: 0..15 → '0'..'f' (hex digits).
-    const T: &[u8; 16] = b"0123456789abcdef";
+fn print_checkout(done: &Checkout) {
+    for path in &done.restored {
+        detail!("restored '{path}'");
+    }
+    for path in &done.removed {
+        detail!("removed '{path}'");
+    }
+}
 
-    // 5) Pre-allocate space for 40 characters (2 hex chars per byte).
-    let mut s = String::with_capacity(out.len() * 2);
+/// A branch name checks out that branch (like `switch`); anything else detaches HEAD.
+fn cmd_checkout(repo: &Repository, rev: &str, force: bool) -> Result<()> {
+    if repo.branch_tip(rev)?.is_some() {
+        return cmd_switch(repo, None, Some(rev), force);
+    }
+    let done = repo.checkout(rev, force)?;
+    print_checkout(&done);
+    say!("HEAD is now at {} {}", &done.commit.id[..7], done.commit.subject());
+    Ok(())
+}
 
-    // 6) For each byte, split into two 4-bit numbers (nibbles) and map to hex.
-    for b in out {
-        // high nibble: top 4 bits → index 0..15 → hex char
-        s.push(T[(b >> 4) as usize] as char);
-        // low nibble: bottom 4 bits → index 0..15 → hex char
-        s.push(T[(b & 0x0f) as usize] as char);
+fn cmd_switch(repo: &Repository, create: Option<&str>, branch: Option<&str>, force: bool) -> Result<()> {
+    if let Some(name) = create {
+        if let Some(done) = repo.switch_new(name, branch, force)? {
+            print_checkout(&done);
+        }
+        say!("Switched to a new branch '{name}'");
+        return Ok(());
+    }
+    let name = branch.context("missing branch name")?;
+    match repo.switch(name, force)? {
+        Some(done) => {
+            print_checkout(&done);
+            say!("Switched to branch '{name}'");
+        }
+        None => say!("Already on '{name}'"),
     }
+    Ok(())
+}
 
-    // 7) Return the 40-char hex string.
-    s
+/* -------- merge -------- */
+
+/// Flags accepted by `mini-git merge`.
+#[derive(clap::Args)]
+struct MergeOptions {
+    /// Show the base version inside conflict markers too
+    #[arg(long)]
+    diff3: bool,
+    /// Message for the merge commit
+    #[arg(short, long)]
+    message: Option<String>,
+    /// Commit a merge once its conflicts are resolved and staged
+    #[arg(long, conflicts_with_all = ["abort", "diff3", "rev"])]
+    r#continue: bool,
+    /// Give up on a conflicted merge and restore HEAD's version of the files it touched
+    #[arg(long, conflicts_with_all = ["message", "diff3", "rev"])]
+    abort: bool,
+    /// Branch or commit to merge into HEAD
+    #[arg(required_unless_present_any = ["continue", "abort"])]
+    rev: Option<String>,
 }
 
-fn to_repo_relative(path: &Path) -> Result<String> {
-    let cwd = std::env::current_dir()?;
-    let abs = if path.is_absolute() { path.to_path_buf() } else { cwd.join(path) };
-    let rel = abs.strip_prefix(&cwd).unwrap_or(&abs);
-    Ok(rel.to_string_lossy().into_owned())
+/// Exits 1 when the merge stops for conflicts.
+fn cmd_merge(repo: &Repository, opts: MergeOptions) -> Result<ExitCode> {
+    // 1) finish or abandon a merge that stopped for conflicts
+    if opts.r#continue {
+        let commit = repo.merge_continue(opts.message)?;
+        say!("[{}] {}", &commit.id[..7], commit.subject());
+        return Ok(ExitCode::SUCCESS);
+    }
+    if opts.abort {
+        print_checkout(&repo.merge_abort()?);
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    // 2) merge
+    let rev = opts.rev.context("missing revision to merge")?;
+    let style = if opts.diff3 { diff::ConflictStyle::Diff3 } else { diff::ConflictStyle::Merge };
+    let old = repo.head()?;
+    match repo.merge(&rev, &mini_git::MergeOptions { style, message: opts.message })? {
+        Merge::UpToDate => say!("Already up to date."),
+        Merge::FastForward(done) => {
+            if let Some(old) = old {
+                say!("Updating {}..{}", &old[..7], &done.commit.id[..7]);
+            }
+            say!("Fast-forward");
+            print_checkout(&done);
+        }
+        Merge::Merged { commit, auto_merged } => {
+            for path in auto_merged {
+                say!("Auto-merging {path}");
+            }
+            say!("[{}] {}", &commit.id[..7], commit.subject());
+        }
+        Merge::Conflicted { conflicts, auto_merged } => {
+            for path in auto_merged {
+                say!("Auto-merging {path}");
+            }
+            for (path, c) in conflicts {
+                match c.code() {
+                    "UD" => println!("CONFLICT (modify/delete): {path} deleted in {rev} and modified in HEAD."),
+                    "DU" => println!("CONFLICT (modify/delete): {path} deleted in HEAD and modified in {rev}."),
+                    "AA" => println!("CONFLICT (add/add): Merge conflict in {path}"),
+                    _ => println!("CONFLICT (content): Merge conflict in {path}"),
+                }
+            }
+            println!("Automatic merge failed; fix conflicts and then run 'mini-git merge --continue'.");
+            return Ok(ExitCode::FAILURE);
+        }
+    }
+    Ok(ExitCode::SUCCESS)
 }
 
-fn stage_file(path: &Path, index: &mut Index) -> Result<()> {
-    // 1) read bytes of the file
-    let data = fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+/* -------- branch -------- */
 
-    // 2) hash bytes → blob id (hex)
-    let blob_id = sha1_hex(&data);
+/// Flags accepted by `mini-git branch`.
+#[derive(clap::Args)]
+struct BranchOptions {
+    /// Delete a branch (it must be merged into HEAD)
+    #[arg(short, long, conflicts_with = "rename")]
+    delete: bool,
+    /// Delete a branch even if it isn't merged
+    #[arg(short = 'D', conflicts_with_all = ["delete", "rename"])]
+    force_delete: bool,
+    /// Rename a branch (the current one when only the new name is given)
+    #[arg(short = 'm', long = "move")]
+    rename: bool,
+    /// Reset an existing branch to <start-point>, or let -m replace an existing branch
+    #[arg(short, long)]
+    force: bool,
+    /// Branch to create, delete or rename
+    name: Option<String>,
+    /// Where a new branch starts (default HEAD); with -m, the new name
+    start_point: Option<String>,
+}
 
-    // 3) write blob once under .minigit/objects/<hash>
-    let obj = objects_dir().join(&blob_id);
-    if !obj.exists() {
-        fs::write(&obj, &data).with_context(|| format!("writing blob {}", blob_id))?;
+fn cmd_branch(repo: &Repository, opts: BranchOptions) -> Result<()> {
+    // 1) delete
+    if opts.delete || opts.force_delete {
+        let name = opts.name.context("branch name required")?;
+        if opts.start_point.is_some() {
+            bail!("branch -d takes a single branch name");
+        }
+        let tip = repo.delete_branch(&name, opts.force_delete)?;
+        say!("Deleted branch {name} (was {}).", &tip[..7]);
+        return Ok(());
     }
 
-    // 4) record staging: repo-relative path → blob id
-    let rel = to_repo_relative(path)?;
-    index.insert(rel, blob_id);
+    // 2) rename: `-m new` renames the current branch, `-m old new` any branch
+    if opts.rename {
+        let (old, new) = match (opts.name, opts.start_point) {
+            (Some(old), Some(new)) => (old, new),
+            (Some(new), None) => match repo.head_ref()? {
+                Head::Branch(current) => (current, new),
+                Head::Detached(_) => bail!("HEAD is detached: name the branch to rename"),
+            },
+            _ => bail!("branch name required"),
+        };
+        repo.rename_branch(&old, &new, opts.force)?;
+        detail!("renamed branch '{old}' to '{new}'");
+        return Ok(());
+    }
 
+    // 3) create
+    if let Some(name) = opts.name {
+        let tip = repo.create_branch(&name, opts.start_point.as_deref(), opts.force)?;
+        detail!("created branch '{name}' at {}", &tip[..7]);
+        return Ok(());
+    }
+
+    // 4) list, marking the current branch (-v adds each tip's short id and subject)
+    let head = repo.head_ref()?;
+    let branches = repo.branches()?;
+    let commits = repo.commits()?;
+    let subject = |id: &str| commits.iter().find(|c| c.id == id).map_or("", |c| c.subject());
+    let verbose = verbosity() >= Verbosity::Verbose;
+    let width = if verbose { branches.iter().map(|(name, _)| name.len()).max().unwrap_or(0) } else { 0 };
+    let line = |mark: &str, label: String, id: &str| {
+        if verbose {
+            println!("{mark} {} {} {}", label, paint(&id[..7], YELLOW), subject(id));
+        } else {
+            println!("{mark} {label}");
+        }
+    };
+    if let Head::Detached(id) = &head {
+        line("*", paint(&format!("{:<width$}", format!("(HEAD detached at {})", &id[..7])), GREEN), id);
+    }
+    for (name, tip) in &branches {
+        if head == Head::Branch(name.clone()) {
+            line("*", paint(&format!("{name:<width$}"), GREEN), tip);
+        } else {
+            line(" ", format!("{name:<width$}"), tip);
+        }
+    }
     Ok(())
 }
 
-fn walkdir(root: &Path) -> Result<Vec<PathBuf>> {
-    let mut out = Vec::new();
-    for entry in fs::read_dir(root)? {
-        let entry = entry?;
-        let p = entry.path();
-        if p.is_dir() {
-            out.extend(walkdir(&p)?);
-        } else if p.is_file() {
-            out.push(p);
+/* -------- tag -------- */
+
+/// Flags accepted by `mini-git tag`.
+#[derive(clap::Args)]
+struct TagOptions {
+    /// Make an annotated tag object (needs -m)
+    #[arg(short, long, requires = "message")]
+    annotate: bool,
+    /// Message for an annotated tag (implies -a)
+    #[arg(short, long)]
+    message: Option<String>,
+    /// List tags, only those matching <name> if it's given (a glob)
+    #[arg(short, long, conflicts_with_all = ["annotate", "message", "force"])]
+    list: bool,
+    /// Delete a tag
+    #[arg(short, long, conflicts_with_all = ["list", "annotate", "message", "force"])]
+    delete: bool,
+    /// Replace an existing tag
+    #[arg(short, long)]
+    force: bool,
+    /// Tag to create or delete (with -l: a pattern)
+    name: Option<String>,
+    /// Commit to tag (default HEAD)
+    rev: Option<String>,
+}
+
+fn cmd_tag(repo: &Repository, opts: TagOptions) -> Result<()> {
+    // 1) delete
+    if opts.delete {
+        let name = opts.name.context("tag name required")?;
+        let id = repo.delete_tag(&name)?;
+        say!("Deleted tag '{name}' (was {})", &id[..7]);
+        return Ok(());
+    }
+
+    // 2) create: lightweight, or annotated with a message
+    if let Some(name) = opts.name.as_deref().filter(|_| !opts.list) {
+        let rev = opts.rev.as_deref().unwrap_or("HEAD");
+        if let Some(old) = repo.create_tag(name, rev, opts.message.as_deref(), opts.force)? {
+            say!("Updated tag '{name}' (was {})", &old[..7]);
         }
+        return Ok(());
     }
-    Ok(out)
-}
 
-fn cmd_init() -> Result<()> {
-    if repo_dir().exists() {
-        println!(".minigit already exists");
+    // 3) list (-v adds the tagged commit and the tag's or commit's subject)
+    let names: Vec<String> = match &opts.name {
+        Some(pattern) => repo.tags()?.into_iter().filter(|t| glob_match(pattern, t)).collect(),
+        None => repo.tags()?,
+    };
+    if verbosity() < Verbosity::Verbose {
+        names.iter().for_each(|name| println!("{name}"));
         return Ok(());
     }
-    fs::create_dir_all(objects_dir())?;
-    save_index(&Index::new())?;
-    println!("Initialized empty mini-git repo in .minigit/");
+    let commits = repo.commits()?;
+    let width = names.iter().map(|n| n.len()).max().unwrap_or(0);
+    for name in &names {
+        let Some(target) = repo.tag_target(name)? else { continue };
+        let subject = match repo.read_tag(&repo.tag_ref(name)?.unwrap_or_default())? {
+            Some(tag) => tag.subject().to_string(),
+            None => commits.iter().find(|c| c.id == target).map_or(String::new(), |c| c.subject().to_string()),
+        };
+        println!("{name:<width$} {} {subject}", paint(&target[..7], YELLOW));
+    }
+    Ok(())
+}
+
+/* -------- rev-parse -------- */
+
+/// Print the object id each revision names, like `git rev-parse`: `a..b` prints `b` and
+/// `^a`; `a...b` prints `b`, `a` and `^<merge base>`.
+fn cmd_rev_parse(repo: &Repository, revs: &[String], short: bool) -> Result<()> {
+    let show = |prefix: &str, id: &str| println!("{prefix}{}", if short { &id[..7] } else { id });
+    for rev in revs {
+        match repo.rev_parse(rev)? {
+            Revision::Single(id) => show("", &id),
+            Revision::Range { from, to } => {
+                show("", &to);
+                show("^", &from);
+            }
+            Revision::Symmetric { left, right, bases } => {
+                show("", &right);
+                show("", &left);
+                bases.iter().for_each(|base| show("^", base));
+            }
+        }
+    }
     Ok(())
 }
 
-fn cmd_add(paths: Vec<PathBuf>) -> Result<()> {
-    ensure_repo()?; // guard: must run `init` first
-    fs::create_dir_all(objects_dir())?; // safe if already exists
+/* -------- merge-base / rev-list -------- */
 
-    let mut index = load_index()?;
+/// Flags accepted by `mini-git merge-base`.
+#[derive(clap::Args)]
+struct MergeBaseOptions {
+    /// Print every best common ancestor, not just one
+    #[arg(short, long)]
+    all: bool,
+    /// Find ancestors common to all the commits at once (as for an octopus merge)
+    #[arg(long, conflicts_with = "is_ancestor")]
+    octopus: bool,
+    /// Print nothing; exit 0 if the first commit is an ancestor of the second, else 1
+    #[arg(long, conflicts_with = "all")]
+    is_ancestor: bool,
+    #[arg(required = true, num_args = 2.., value_name = "commit")]
+    commits: Vec<String>,
+}
 
-    for p in paths {
-        if p.is_dir() {
-            for f in walkdir(&p)? {
-                stage_file(&f, &mut index)?;
+/// Exits 1 when there's no common ancestor (or, with --is-ancestor, when it isn't one).
+fn cmd_merge_base(repo: &Repository, opts: MergeBaseOptions) -> Result<ExitCode> {
+    let ids = opts.commits.iter().map(|rev| Ok(repo.find_commit(rev)?.id)).collect::<Result<Vec<String>>>()?;
+    let ids: Vec<&str> = ids.iter().map(String::as_str).collect();
+    let graph = repo.commit_graph()?;
+    if opts.is_ancestor {
+        let [ancestor, descendant] = ids[..] else { bail!("--is-ancestor takes exactly two commits") };
+        return Ok(if graph.is_ancestor(ancestor, descendant) { ExitCode::SUCCESS } else { ExitCode::FAILURE });
+    }
+    let bases = if opts.octopus { graph.octopus_merge_bases(&ids) } else { graph.merge_bases(ids[0], &ids[1..]) };
+    let shown = if opts.all { &bases[..] } else { &bases[..bases.len().min(1)] };
+    shown.iter().for_each(|base| println!("{base}"));
+    Ok(if bases.is_empty() { ExitCode::FAILURE } else { ExitCode::SUCCESS })
+}
+
+/// Flags accepted by `mini-git rev-list`.
+#[derive(clap::Args)]
+struct RevListOptions {
+    /// Print how many commits there are instead of listing them
+    #[arg(long)]
+    count: bool,
+    /// Never show a parent before its children, and keep each line of history together
+    #[arg(long, conflicts_with = "date_order")]
+    topo_order: bool,
+    /// Never show a parent before its children; otherwise newest first
+    #[arg(long)]
+    date_order: bool,
+    /// Only commits on a path from an excluded commit to an included one
+    #[arg(long)]
+    ancestry_path: bool,
+    /// Commits to list the history of; ^<rev> excludes a commit's history, a..b and a...b work too
+    #[arg(required = true, value_name = "rev")]
+    revs: Vec<String>,
+}
+
+fn cmd_rev_list(repo: &Repository, opts: RevListOptions) -> Result<()> {
+    // 1) split the revisions into the histories to list and the ones to leave out
+    let (mut include, mut exclude) = (Vec::new(), Vec::new());
+    for rev in &opts.revs {
+        if let Some(rev) = rev.strip_prefix('^') {
+            exclude.push(repo.find_commit(rev)?.id);
+            continue;
+        }
+        match repo.rev_parse(rev)? {
+            Revision::Single(id) => include.push(repo.peel_to_commit(&id)?.id),
+            Revision::Range { from, to } => {
+                include.push(to);
+                exclude.push(from);
             }
-        } else if p.is_file() {
-            stage_file(&p, &mut index)?;
+            Revision::Symmetric { left, right, bases } => {
+                include.extend([left, right]);
+                exclude.extend(bases);
+            }
+        }
+    }
+    if opts.ancestry_path && exclude.is_empty() {
+        bail!("--ancestry-path needs a commit to start from (^<rev> or a..b)");
+    }
+
+    // 2) walk the graph
+    let order = match (opts.topo_order, opts.date_order) {
+        (true, _) => RevOrder::Topo,
+        (_, true) => RevOrder::Date,
+        _ => RevOrder::Chronological,
+    };
+    let flags = mini_git::RevListOptions { order, ancestry_path: opts.ancestry_path };
+    let include: Vec<&str> = include.iter().map(String::as_str).collect();
+    let exclude: Vec<&str> = exclude.iter().map(String::as_str).collect();
+    let commits = repo.commit_graph()?.rev_list(&include, &exclude, flags);
+    if opts.count {
+        println!("{}", commits.len());
+    } else {
+        commits.iter().for_each(|id| println!("{id}"));
+    }
+    Ok(())
+}
+
+/* -------- rm / mv -------- */
+
+fn cmd_rm(repo: &Repository, paths: &[PathBuf], cached: bool, recursive: bool, force: bool) -> Result<()> {
+    for path in repo.rm(paths, cached, recursive, force)? {
+        say!("rm '{path}'");
+    }
+    Ok(())
+}
+
+fn cmd_mv(repo: &Repository, src: &Path, dst: &Path, force: bool) -> Result<()> {
+    let moved = repo.mv(src, dst, force)?;
+    for (old, new) in &moved.renamed {
+        detail!("rename '{old}' -> '{new}'");
+    }
+    say!("Renamed '{}' -> '{}'.", moved.from, moved.to);
+    Ok(())
+}
+
+/* -------- add -p -------- */
+
+/// Let the user rewrite hunk `i` in $EDITOR. Returns whether the edit applied (if not, the
+/// hunk is left as it was).
+fn edit_hunk(repo: &Repository, patch: &mut FilePatch, i: usize) -> Result<bool> {
+    use std::io::Write;
+
+    // 1) write the hunk plus a short guide to a scratch file
+    let file = repo.git_dir().join("ADD_EDIT.hunk");
+    let mut text = format!("# Manual hunk edit mode -- see bottom for a quick guide.\n{}\n", patch.hunk_header(i)).into_bytes();
+    text.extend_from_slice(&patch.hunk_text(i));
+    text.extend_from_slice(
+        b"# ---\n\
+          # To remove '-' lines, make them ' ' lines (context).\n\
+          # To remove '+' lines, delete them.\n\
+          # Lines starting with # will be removed.\n",
+    );
+    fs::File::create(&file)
+        .and_then(|mut f| f.write_all(&text))
+        .with_context(|| format!("writing {}", file.display()))?;
+
+    // 2) run the editor ($MINIGIT_EDITOR, $VISUAL, $EDITOR, else vi) through the shell so it may carry arguments
+    let editor = ["MINIGIT_EDITOR", "VISUAL", "EDITOR"]
+        .iter()
+        .find_map(|var| std::env::var(var).ok().filter(|v| !v.is_empty()))
+        .unwrap_or_else(|| "vi".to_string());
+    let status = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(format!("{editor} \"$1\""))
+        .arg(&editor)
+        .arg(&file)
+        .status()
+        .with_context(|| format!("running editor {editor}"))?;
+    let edited = fs::read(&file).with_context(|| format!("reading {}", file.display()))?;
+    let _ = fs::remove_file(&file);
+    if !status.success() {
+        bail!("editor {editor} exited with {status}");
+    }
+
+    // 3) apply it, if it still fits the old side of the hunk
+    Ok(patch.edit_hunk(i, &edited))
+}
+
+/// `add -p`: walk the hunks between each tracked file's staged blob and the working
+/// tree, and stage only the ones picked.
+fn add_patch(repo: &Repository, paths: &[PathBuf]) -> Result<()> {
+    use std::io::{BufRead, Write};
+
+    let lock = repo.lock_index()?;
+    let mut index = repo.load_index()?;
+    let patches = repo.file_patches(&index, paths)?;
+
+    let mut input = std::io::stdin().lock().lines();
+    let mut files_staged = 0;
+    let mut quit = false;
+    for mut patch in patches {
+        // 1) only content changes to text files can be staged piecemeal
+        let rel = patch.path.clone();
+        if patch.binary {
+            say!("Skipping {rel}: not a text file (use `add {rel}` to stage it whole)");
+            continue;
+        }
+        println!("{}", paint(&format!("--- a/{rel}\n+++ b/{rel}"), BOLD));
+
+        // 2) ask about each hunk; `a` / `d` answer for the rest of this file
+        let mut rest_of_file: Option<char> = None;
+        let mut i = 0;
+        while i < patch.hunks.len() {
+            let splittable = patch.can_split(i);
+            let answer = match rest_of_file {
+                Some(c) => c,
+                None => {
+                    println!("{}", paint(&patch.hunk_header(i), CYAN));
+                    for l in patch.hunk(i) {
+                        print_diff_line(l.op, &l.text);
+                    }
+                    let choices = if splittable { "y,n,q,a,d,s,e,?" } else { "y,n,q,a,d,e,?" };
+                    print!("{}", paint(&format!("({}/{}) Stage this hunk [{choices}]? ", i + 1, patch.hunks.len()), BOLD));
+                    std::io::stdout().flush()?;
+                    match input.next() {
+                        Some(line) => line?.trim().chars().next().unwrap_or(' '),
+                        None => 'q', // end of input: keep what was picked so far
+                    }
+                }
+            };
+            match answer {
+                'y' => {
+                    patch.stage_hunk(i);
+                    i += 1;
+                }
+                'n' => i += 1,
+                'a' => rest_of_file = Some('y'),
+                'd' => rest_of_file = Some('n'),
+                'q' => {
+                    quit = true;
+                    break;
+                }
+                's' if splittable => println!("Split into {} hunks.", patch.split_hunk(i)),
+                'e' => {
+                    if edit_hunk(repo, &mut patch, i)? {
+                        i += 1;
+                    } else {
+                        eprintln!("Your edited hunk does not apply; try again.");
+                    }
+                }
+                _ => println!(
+                    "y - stage this hunk\n\
+                     n - do not stage this hunk\n\
+                     q - quit; do not stage this hunk or any of the remaining ones\n\
+                     a - stage this hunk and all later hunks in the file\n\
+                     d - do not stage this hunk or any of the later hunks in the file\n\
+                     s - split the current hunk into smaller hunks\n\
+                     e - manually edit the current hunk\n\
+                     ? - print help"
+                ),
+            }
+        }
+
+        // 3) stage the picked changes
+        if repo.stage_partial(&mut index, &patch)? {
+            files_staged += 1;
+        }
+        if quit {
+            break;
+        }
+    }
+
+    repo.save_index(&index, lock)?;
+    say!("Staged hunks in {files_staged} file(s).");
+    Ok(())
+}
+
+/* -------- diff -------- */
+
+/// Print one diff line as ` text`, `-text` or `+text`, colored, with git's marker for
+/// a final line that has no newline.
+fn print_diff_line(op: diff::Op, text: &[u8]) {
+    let (prefix, color) = match op {
+        diff::Op::Equal => (' ', ""),
+        diff::Op::Delete => ('-', RED),
+        diff::Op::Insert => ('+', GREEN),
+    };
+    let line = format!("{prefix}{}", String::from_utf8_lossy(text.strip_suffix(b"\n").unwrap_or(text)));
+    println!("{}", if color.is_empty() { line } else { paint(&line, color) });
+    if !text.ends_with(b"\n") {
+        println!("\\ No newline at end of file");
+    }
+}
+
+/// Print a hunk as a word diff: changed words inline as `[-old-]{+new+}`.
+fn print_word_diff(old: &[u8], new: &[u8], opts: &diff::Options) {
+    let mut line = String::new();
+    for (op, text) in diff::diff_words(old, new, opts) {
+        // markers can't span lines: close them at each newline and reopen on the next line
+        for piece in text.split_inclusive(|&b| b == b'\n') {
+            let body = String::from_utf8_lossy(piece.strip_suffix(b"\n").unwrap_or(piece));
+            if !body.is_empty() {
+                match op {
+                    diff::Op::Equal => line.push_str(&body),
+                    diff::Op::Delete => line.push_str(&paint(&format!("[-{body}-]"), RED)),
+                    diff::Op::Insert => line.push_str(&paint(&format!("{{+{body}+}}"), GREEN)),
+                }
+            }
+            if piece.ends_with(b"\n") {
+                println!("{line}");
+                line.clear();
+            }
+        }
+    }
+    if !line.is_empty() {
+        println!("{line}");
+    }
+}
+
+/// Flags accepted by `mini-git diff`.
+#[derive(clap::Args)]
+struct DiffOptions {
+    /// Compare the index with HEAD (or with <commit>) instead of the working tree with the index
+    #[arg(long, visible_alias = "staged")]
+    cached: bool,
+    /// Lines of context around each change
+    #[arg(short = 'U', long = "unified", value_name = "n", default_value_t = 3)]
+    context: usize,
+    /// Per-file counts of added and removed lines instead of the patch
+    #[arg(long, conflicts_with_all = ["name_only", "name_status"])]
+    stat: bool,
+    /// Only list the changed paths
+    #[arg(long, conflicts_with = "name_status")]
+    name_only: bool,
+    /// List the changed paths with A (added), D (deleted) or M (modified)
+    #[arg(long)]
+    name_status: bool,
+    /// How to line up old and new lines: myers, patience or histogram
+    #[arg(long, value_name = "algorithm", default_value = "myers")]
+    diff_algorithm: diff::Algorithm,
+    /// Ignore whitespace when comparing lines
+    #[arg(short = 'w', long)]
+    ignore_all_space: bool,
+    /// Ignore changes in the amount of whitespace
+    #[arg(short = 'b', long)]
+    ignore_space_change: bool,
+    /// Ignore whitespace at the end of lines
+    #[arg(long)]
+    ignore_space_at_eol: bool,
+    /// Show changed words inline as [-removed-]{+added+} instead of whole lines
+    #[arg(long)]
+    word_diff: bool,
+    /// None: index → worktree; one: <commit> → worktree (or index with --cached); two: <a> → <b>.
+    /// Arguments after those that name files rather than commits are paths, as after `--`
+    #[arg(value_name = "commit")]
+    commits: Vec<String>,
+    /// Only show changes to these paths (files, directories or globs)
+    #[arg(last = true, value_name = "path")]
+    paths: Vec<PathBuf>,
+}
+
+impl DiffOptions {
+    /// Settings for the diff engine.
+    fn engine(&self) -> diff::Options {
+        let whitespace = if self.ignore_all_space {
+            diff::Whitespace::IgnoreAll
+        } else if self.ignore_space_change {
+            diff::Whitespace::IgnoreChange
+        } else if self.ignore_space_at_eol {
+            diff::Whitespace::IgnoreAtEol
         } else {
-            eprintln!("Skipping (not found): {}", p.display());
+            diff::Whitespace::Exact
+        };
+        diff::Options { algorithm: self.diff_algorithm, whitespace }
+    }
+}
+
+/// `diff --git` headers plus unified hunks for one file (or git's one-line note for binaries).
+fn print_patch(d: &FileDiff, opts: &DiffOptions) {
+    let path = &d.path;
+    let (old, new) = (d.old.as_ref(), d.new.as_ref());
+    let short = |e: Option<&FileEntry>| e.map_or("0000000".to_string(), |e| e.blob[..7].to_string());
+    println!("{}", paint(&format!("diff --git a/{path} b/{path}"), BOLD));
+    match (old, new) {
+        (None, Some(n)) => println!("{}", paint(&format!("new file mode {:o}", n.mode), BOLD)),
+        (Some(o), None) => println!("{}", paint(&format!("deleted file mode {:o}", o.mode), BOLD)),
+        (Some(o), Some(n)) if o.mode != n.mode => {
+            println!("{}", paint(&format!("old mode {:o}\nnew mode {:o}", o.mode, n.mode), BOLD));
         }
+        _ => {}
     }
+    let same_mode = matches!((old, new), (Some(o), Some(n)) if o.mode == n.mode);
+    if old.map(|e| &e.blob) == new.map(|e| &e.blob) {
+        return; // mode change only
+    }
+    let mode = if same_mode { format!(" {:o}", new.map_or(0, |e| e.mode)) } else { String::new() };
+    println!("{}", paint(&format!("index {}..{}{mode}", short(old), short(new)), BOLD));
+    let (a, b) = (
+        old.map_or("/dev/null".to_string(), |_| format!("a/{path}")),
+        new.map_or("/dev/null".to_string(), |_| format!("b/{path}")),
+    );
+    if d.is_binary() {
+        println!("Binary files {a} and {b} differ");
+        return;
+    }
+    println!("{}", paint(&format!("--- {a}\n+++ {b}"), BOLD));
+    let engine = opts.engine();
+    let (old_lines, new_lines, edits) = d.script(&engine);
+    let ops: Vec<diff::Op> = edits.iter().map(|e| e.op).collect();
+    for range in diff::hunk_ranges(&ops, opts.context) {
+        println!("{}", paint(&diff::hunk_header(&ops, range.clone()), CYAN));
+        let hunk = &edits[range];
+        if opts.word_diff {
+            // each side of the hunk as one text; equal lines are taken from their own side
+            let old: Vec<u8> = hunk.iter().filter(|e| e.op != diff::Op::Insert).flat_map(|e| old_lines[e.old].to_vec()).collect();
+            let new: Vec<u8> = hunk.iter().filter(|e| e.op != diff::Op::Delete).flat_map(|e| new_lines[e.new].to_vec()).collect();
+            print_word_diff(&old, &new, &engine);
+        } else {
+            for e in hunk {
+                print_diff_line(e.op, e.text(&old_lines, &new_lines));
+            }
+        }
+    }
+}
 
-    save_index(&index)?;
-    println!("Staged {} path(s).", index.len());
+/// ` path | 5 +++--` per file and a totals line, like `git diff --stat`.
+fn print_stat(diffs: &[FileDiff], opts: &diff::Options) {
+    const BAR: usize = 40;
+    let counts: Vec<Option<(usize, usize)>> = diffs
+        .iter()
+        .map(|d| {
+            (!d.is_binary()).then(|| {
+                let edits = d.script(opts).2;
+                let n = |want: diff::Op| edits.iter().filter(|e| e.op == want).count();
+                (n(diff::Op::Insert), n(diff::Op::Delete))
+            })
+        })
+        .collect();
+    let width = diffs.iter().map(|d| d.path.len()).max().unwrap_or(0);
+    let most = counts.iter().flatten().map(|(i, d)| i + d).max().unwrap_or(0);
+    let scale = |n: usize| if most <= BAR || n == 0 { n } else { (n * BAR / most).max(1) };
+    // the count column fits the largest total (or "Bin")
+    let digits = most.to_string().len().max(if counts.contains(&None) { 3 } else { 1 });
+    let (mut insertions, mut deletions) = (0, 0);
+    for (d, count) in diffs.iter().zip(&counts) {
+        match count {
+            None => println!(" {:<width$} | Bin {} -> {} bytes", d.path, d.old_data.len(), d.new_data.len()),
+            Some((ins, del)) => {
+                insertions += ins;
+                deletions += del;
+                let bar = format!("{}{}", paint(&"+".repeat(scale(*ins)), GREEN), paint(&"-".repeat(scale(*del)), RED));
+                let line = format!(" {:<width$} | {:>digits$} {bar}", d.path, ins + del);
+                println!("{}", line.trim_end());
+            }
+        }
+    }
+    let plural = |n: usize, one: &str, many: &str| format!("{n} {}", if n == 1 { one } else { many });
+    let mut summary = vec![plural(diffs.len(), "file changed", "files changed")];
+    if insertions > 0 {
+        summary.push(plural(insertions, "insertion(+)", "insertions(+)"));
+    }
+    if deletions > 0 {
+        summary.push(plural(deletions, "deletion(-)", "deletions(-)"));
+    }
+    println!(" {}", summary.join(", "));
+}
+
+fn cmd_diff(repo: &Repository, opts: DiffOptions) -> Result<()> {
+    // 1) split off paths given without `--`: like git, the first argument that isn't a
+    //    revision but exists in the worktree (or is a glob) starts them
+    let mut revs = Vec::new();
+    let mut paths = Vec::new();
+    for arg in &opts.commits {
+        let is_rev = paths.is_empty()
+            && revs.len() < 2
+            && match repo.find_commit(arg) {
+                Ok(_) => true,
+                Err(mini_git::Error::UnknownRevision(_)) => false,
+                Err(e) => return Err(e.into()),
+            };
+        let path = PathBuf::from(arg);
+        if is_rev {
+            revs.push(arg.clone());
+        } else if is_pathspec_glob(&path) || fs::symlink_metadata(repo.resolve(&path)).is_ok() {
+            paths.push(path);
+        } else {
+            bail!("unknown revision or path not in the working tree: {arg}\nUse '--' to separate paths from revisions: mini-git diff [<commit>...] -- <path>...");
+        }
+    }
+    paths.extend(opts.paths.iter().cloned());
+
+    // 2) pick the two sides
+    let (old, new) = match (revs.as_slice(), opts.cached) {
+        ([], false) => (DiffSide::index(repo)?, DiffSide::worktree(repo)?),
+        ([], true) => (DiffSide::tree(repo.head_tree()?), DiffSide::index(repo)?),
+        ([rev], false) => (DiffSide::commit(repo, rev)?, DiffSide::worktree(repo)?),
+        ([rev], true) => (DiffSide::commit(repo, rev)?, DiffSide::index(repo)?),
+        ([a, b], false) => (DiffSide::commit(repo, a)?, DiffSide::commit(repo, b)?),
+        _ => bail!("--cached compares the index with at most one commit"),
+    };
+
+    // 3) changed paths, limited to the pathspec (contents are only needed for patches and --stat)
+    let mut diffs = repo.diff_trees(&old, &new, &paths, !opts.name_only && !opts.name_status)?;
+
+    // 4) print in the requested format
+    if opts.name_only {
+        diffs.iter().for_each(|d| println!("{}", d.path));
+    } else if opts.name_status {
+        diffs.iter().for_each(|d| println!("{}\t{}", d.letter(), d.path));
+    } else {
+        // with whitespace ignored, a file may have nothing left to show
+        let engine = opts.engine();
+        diffs.retain(|d| d.shows_changes(&engine));
+        if !opts.stat {
+            diffs.iter().for_each(|d| print_patch(d, &opts));
+        } else if !diffs.is_empty() {
+            print_stat(&diffs, &engine);
+        }
+    }
     Ok(())
 }
 
-fn main() -> Result<()> {
-    let mut args = std::env::args().skip(1);
-    match args.next().as_deref() {
-        Some("init") => cmd_init(),
-        Some("add") => {
-            let paths: Vec<PathBuf> = args.map(PathBuf::from).collect();
-            if paths.is_empty() {
-                bail!("Usage: mini-git add <files-or-dirs>");
+/* -------- CLI -------- */
+
+#[derive(Parser)]
+#[command(name = "mini-git", version, about = "A tiny, learning-focused version control tool")]
+struct Cli {
+    /// Run as if mini-git was started in <dir>
+    #[arg(short = 'C', global = true, value_name = "dir")]
+    dir: Option<PathBuf>,
+    /// Use <path> as the repository directory instead of searching for .minigit (overrides $MINIGIT_DIR)
+    #[arg(long, global = true, value_name = "path")]
+    git_dir: Option<PathBuf>,
+    /// Only print errors
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+    /// Print extra detail (e.g., every path staged or restored)
+    #[arg(short, long, global = true)]
+    verbose: bool,
+    /// When to color output
+    #[arg(long, global = true, value_enum, default_value_t = ColorWhen::Auto, value_name = "when")]
+    color: ColorWhen,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ColorWhen {
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Create an empty repository in .minigit/
+    Init {
+        /// Hash algorithm for object ids: sha1, sha256 or blake3
+        #[arg(long, default_value = "sha1", value_name = "format")]
+        object_format: ObjectFormat,
+        /// Hash and store objects exactly like git, so real git tooling can read the repository
+        #[arg(long)]
+        git_compat: bool,
+    },
+    /// Stage files or directories (recursively), including deletions
+    Add(AddOptions),
+    /// Remove paths from the index and the working tree
+    Rm {
+        #[arg(required = true, value_name = "paths")]
+        paths: Vec<PathBuf>,
+        /// Only unstage; keep the files in the working tree
+        #[arg(long)]
+        cached: bool,
+        /// Allow removing a directory's tracked files recursively
+        #[arg(short)]
+        recursive: bool,
+        /// Remove even if that loses unstaged or uncommitted changes
+        #[arg(short, long)]
+        force: bool,
+    },
+    /// Move or rename a tracked file or directory
+    Mv {
+        src: PathBuf,
+        dst: PathBuf,
+        /// Overwrite an existing destination file
+        #[arg(short, long)]
+        force: bool,
+    },
+    /// Record the index as a new commit
+    Commit {
+        /// Commit message
+        #[arg(short, long)]
+        message: String,
+    },
+    /// Show commit history from HEAD
+    Log(LogOptions),
+    /// Show changes between the working tree, the index and commits as unified diffs
+    Diff(DiffOptions),
+    /// Show staged, unstaged and untracked changes
+    Status {
+        /// Machine-readable `XY path` output
+        #[arg(long)]
+        porcelain: bool,
+    },
+    /// Switch to a branch, or restore the working tree and index to a commit (detaching HEAD)
+    Checkout {
+        /// Discard local changes that would be overwritten
+        #[arg(short, long)]
+        force: bool,
+        commit: String,
+    },
+    /// List, create, delete or rename branches (-v shows each tip commit)
+    Branch(BranchOptions),
+    /// Create, list (-l) or delete (-d) tags; -a -m makes an annotated tag
+    Tag(TagOptions),
+    /// Switch to a branch, or create one with -c
+    Switch {
+        /// Create <new-branch> (at <branch>, or HEAD) and switch to it
+        #[arg(short = 'c', long, value_name = "new-branch")]
+        create: Option<String>,
+        /// Branch to switch to (with -c: where the new branch starts)
+        #[arg(required_unless_present = "create", value_name = "branch")]
+        branch: Option<String>,
+        /// Discard local changes that would be overwritten
+        #[arg(short, long)]
+        force: bool,
+    },
+    /// Join another branch's history into HEAD (--continue / --abort a conflicted merge)
+    Merge(MergeOptions),
+    /// List the paths in the index
+    LsFiles {
+        /// Show each path's mode and blob id
+        #[arg(short, long)]
+        stage: bool,
+        /// Dump the whole index, including cached stat data, as JSON (for debugging)
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print the object id a file's content would get (plumbing)
+    HashObject {
+        /// Object type to hash as
+        #[arg(short = 't', default_value = "blob", value_parser = ["blob", "tree", "commit", "tag"])]
+        kind: String,
+        /// Also write the object into the object store
+        #[arg(short)]
+        write: bool,
+        file: PathBuf,
+    },
+    /// Compress objects written by older versions of mini-git
+    MigrateObjects,
+    /// Print the object ids revisions name: HEAD~2, main^2, v1.0, abc123, a..b, HEAD:path (plumbing)
+    RevParse {
+        /// Abbreviate ids to 7 hex digits
+        #[arg(long)]
+        short: bool,
+        #[arg(required = true, value_name = "rev")]
+        revs: Vec<String>,
+    },
+    /// Find the best common ancestors of commits, or test ancestry (--is-ancestor)
+    MergeBase(MergeBaseOptions),
+    /// List the commits reachable from some revisions and not others (plumbing)
+    RevList(RevListOptions),
+    /// Check object integrity; --migrate moves objects into fan-out directories
+    Fsck {
+        /// Move objects from the old flat layout into objects/ab/cdef...
+        #[arg(long)]
+        migrate: bool,
+    },
+    /// Show which .minigitignore rule (if any) matches each path
+    CheckIgnore {
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+    },
+    /// Print a shell completion script
+    Completions {
+        #[arg(value_enum)]
+        shell: clap_complete::Shell,
+    },
+}
+
+fn run(cli: Cli) -> Result<ExitCode> {
+    // relative paths are taken from here; the process's own directory is never changed
+    let mut cwd = std::env::current_dir().with_context(|| "reading the current directory")?;
+    if let Some(dir) = &cli.dir {
+        cwd = cwd.join(dir).canonicalize().with_context(|| format!("cannot change to {}", dir.display()))?;
+        if !cwd.is_dir() {
+            bail!("cannot change to {}: not a directory", dir.display());
+        }
+    }
+    let git_dir = explicit_git_dir(&cwd, cli.git_dir.as_deref());
+    let repo = || open_repo(&cwd, git_dir.as_deref());
+    match cli.command {
+        Command::Init { object_format, git_compat } => cmd_init(&cwd, git_dir, object_format, git_compat)?,
+        Command::Add(opts) => cmd_add(&repo()?, opts)?,
+        Command::Rm { paths, cached, recursive, force } => cmd_rm(&repo()?, &paths, cached, recursive, force)?,
+        Command::Mv { src, dst, force } => cmd_mv(&repo()?, &src, &dst, force)?,
+        Command::Commit { message } => cmd_commit(&repo()?, message)?,
+        Command::Log(opts) => cmd_log(&repo()?, opts)?,
+        Command::Diff(opts) => cmd_diff(&repo()?, opts)?,
+        Command::Status { porcelain } => cmd_status(&repo()?, porcelain)?,
+        Command::Checkout { force, commit } => cmd_checkout(&repo()?, &commit, force)?,
+        Command::Branch(opts) => cmd_branch(&repo()?, opts)?,
+        Command::Tag(opts) => cmd_tag(&repo()?, opts)?,
+        Command::Switch { create, branch, force } => cmd_switch(&repo()?, create.as_deref(), branch.as_deref(), force)?,
+        Command::Merge(opts) => return cmd_merge(&repo()?, opts),
+        Command::LsFiles { stage, json } => cmd_ls_files(&repo()?, stage, json)?,
+        Command::HashObject { kind, write, file } => cmd_hash_object(&repo()?, &file, &kind, write)?,
+        Command::MigrateObjects => cmd_migrate_objects(&repo()?)?,
+        Command::RevParse { short, revs } => cmd_rev_parse(&repo()?, &revs, short)?,
+        Command::MergeBase(opts) => return cmd_merge_base(&repo()?, opts),
+        Command::RevList(opts) => cmd_rev_list(&repo()?, opts)?,
+        Command::Fsck { migrate } => cmd_fsck(&repo()?, migrate)?,
+        Command::CheckIgnore { paths } => return cmd_check_ignore(&repo()?, &paths),
+        Command::Completions { shell } => {
+            clap_complete::generate(shell, &mut Cli::command(), "mini-git", &mut std::io::stdout());
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Print the rule deciding each path (`source:line:pattern<TAB>path`).
+/// Exit status is 0 if at least one path is ignored, 1 otherwise (like `git check-ignore -v`).
+fn cmd_check_ignore(repo: &Repository, paths: &[PathBuf]) -> Result<ExitCode> {
+    let mut ignore = Ignore::new(repo.worktree());
+    let mut any = false;
+    for p in paths {
+        let rel = repo.to_repo_relative(p)?;
+        if let Some(rule) = ignore.explain(&rel, repo.resolve(p).is_dir())? {
+            println!("{}:{}:{}\t{}", rule.source, rule.line, rule.text, p.display());
+            any |= !rule.negated;
+        }
+    }
+    Ok(if any { ExitCode::SUCCESS } else { ExitCode::FAILURE })
+}
+
+/// Print the id `data` would get as an object of `kind` in this repository; with `write`, store it too.
+fn cmd_hash_object(repo: &Repository, path: &Path, kind: &str, write: bool) -> Result<()> {
+    let data = fs::read(repo.resolve(path)).with_context(|| format!("reading {}", path.display()))?;
+    let id = if write { repo.objects().write(kind, &data)? } else { repo.objects().hash(kind, &data) };
+    println!("{id}");
+    Ok(())
+}
+
+/// Rewrite raw (pre-compression) objects in the compressed, typed format.
+fn cmd_migrate_objects(repo: &Repository) -> Result<()> {
+    let migrated = repo.objects().compress_legacy()?;
+    for id in &migrated {
+        detail!("compressed {id}");
+    }
+    say!("Compressed {} object(s).", migrated.len());
+    Ok(())
+}
+
+/// Verify every object's content matches its id, that everything the index and commits
+/// reference exists, and report objects still in the old flat layout.
+/// With `migrate`, flat objects are moved into their `objects/ab/` shard.
+fn cmd_fsck(repo: &Repository, migrate: bool) -> Result<()> {
+    let report = repo.fsck(migrate)?;
+    for (id, actual) in &report.corrupt {
+        eprintln!("corrupt object {id}: content hashes to {actual}");
+    }
+    if migrate {
+        for id in &report.flat {
+            detail!("moved {id}");
+        }
+    }
+    for (blob, user) in &report.missing {
+        eprintln!("missing blob {blob} (referenced by {user})");
+    }
+
+    let flat = report.flat.len();
+    if flat > 0 {
+        if migrate {
+            say!("Moved {flat} object(s) into fan-out directories.");
+        } else {
+            say!("{flat} object(s) use the old flat layout; run `mini-git fsck --migrate` to move them.");
+        }
+    }
+    if report.problems() > 0 {
+        bail!("fsck found {} problem(s)", report.problems());
+    }
+    say!("ok");
+    Ok(())
+}
+
+/// List staged paths; `--stage` adds modes and blob ids (and, for paths with merge conflicts,
+/// each side's stage number), `--json` dumps every entry with its stat data.
+fn cmd_ls_files(repo: &Repository, stage: bool, json: bool) -> Result<()> {
+    let index = repo.load_index()?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&index).with_context(|| "serializing index to JSON")?);
+        return Ok(());
+    }
+    let paths: BTreeSet<&String> = index.keys().chain(index.conflicts.keys()).collect();
+    for path in paths {
+        if !stage {
+            println!("{path}");
+        } else if let Some(conflict) = index.conflicts.get(path) {
+            for (n, e) in conflict.stages() {
+                println!("{:o} {} {n} {path}", e.mode, e.blob);
             }
-            cmd_add(paths)
+        } else {
+            let e = &index[path];
+            println!("{:o} {} {}", e.mode, e.blob, path);
         }
-        _ => {
-            eprintln!("Usage:");
-            eprintln!("  mini-git init");
-            eprintln!("  mini-git add <files-or-dirs>");
-            Ok(())
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    // clap prints usage errors itself and exits with status 2
+    let cli = Cli::parse();
+
+    let level = match (cli.quiet, cli.verbose) {
+        (true, _) => Verbosity::Quiet,
+        (_, true) => Verbosity::Verbose,
+        _ => Verbosity::Normal,
+    };
+    let color = match cli.color {
+        ColorWhen::Always => true,
+        ColorWhen::Never => false,
+        ColorWhen::Auto => std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none(),
+    };
+    let _ = VERBOSITY.set(level);
+    let _ = USE_COLOR.set(color);
+
+    match run(cli) {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("error: {err:#}");
+            ExitCode::FAILURE
         }
     }
 }
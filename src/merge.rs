@@ -0,0 +1,391 @@
+//! Merging another line of history into the current one: fast-forwards, three-way merges of
+//! every path against the merge base, and the state an unfinished merge leaves behind
+//! (`.minigit/MERGE_HEAD` and `.minigit/MERGE_MSG`, plus conflict stages in the index).
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::commit::Commit;
+use crate::diff::{is_binary, merge3, ConflictStyle, MergeLabels};
+use crate::error::{Context, Error, Result};
+use crate::index::{Conflict, FileEntry, FileStat, IndexEntry, MODE_SYMLINK};
+use crate::repository::Repository;
+use crate::util::write_atomic;
+use crate::worktree::Checkout;
+
+/// How `merge` should go about it.
+#[derive(Clone, Debug, Default)]
+pub struct MergeOptions {
+    pub style: ConflictStyle,
+    /// The merge commit's message (default: "Merge branch '<name>'").
+    pub message: Option<String>,
+}
+
+/// What `merge` did.
+#[derive(Debug)]
+pub enum Merge {
+    /// The other side is already part of HEAD's history.
+    UpToDate,
+    /// HEAD's history is part of the other side's, so HEAD just moved there.
+    FastForward(Checkout),
+    /// A merge commit, with the paths whose contents were merged line by line.
+    Merged { commit: Commit, auto_merged: Vec<String> },
+    /// Stopped with conflicts for the user to resolve before `merge --continue`.
+    Conflicted { conflicts: Vec<(String, Conflict)>, auto_merged: Vec<String> },
+}
+
+/// One path's merge result.
+enum Resolution {
+    Clean(Option<FileEntry>),
+    /// Merged text with (or, when `conflict` is None, without) conflict markers.
+    Content { mode: u32, text: Vec<u8>, conflict: Option<Conflict> },
+    /// A conflict the worktree can only show one side of (binary files, deletions).
+    Unmergeable { keep: Option<FileEntry>, conflict: Conflict },
+}
+
+impl Resolution {
+    /// Whether the result is a file in the worktree (rather than a deletion).
+    fn writes_file(&self) -> bool {
+        match self {
+            Resolution::Clean(entry) | Resolution::Unmergeable { keep: entry, .. } => entry.is_some(),
+            Resolution::Content { .. } => true,
+        }
+    }
+}
+
+impl Repository {
+    fn merge_head_path(&self) -> PathBuf {
+        self.git_dir().join("MERGE_HEAD")
+    }
+
+    fn merge_msg_path(&self) -> PathBuf {
+        self.git_dir().join("MERGE_MSG")
+    }
+
+    /// The commit being merged in, while a merge is waiting for `merge --continue`.
+    pub fn merge_head(&self) -> Result<Option<String>> {
+        self.read_ref(&self.merge_head_path(), || "MERGE_HEAD".to_string())
+    }
+
+    /// Forget an unfinished merge (its commit was made, or it was abandoned).
+    pub(crate) fn clear_merge_state(&self) -> Result<()> {
+        for path in [self.merge_head_path(), self.merge_msg_path()] {
+            match fs::remove_file(&path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                    return Err(e).with_context(|| format!("removing {}", path.display()));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Merge revision `rev` into HEAD: fast-forward if HEAD is its ancestor, otherwise
+    /// merge each path three ways against the merge base and commit the result. With
+    /// conflicts, the worktree gets conflict markers, the index records every side, and
+    /// the merge waits for [`Repository::merge_continue`]. When there are several merge
+    /// bases, the newest is used.
+    pub fn merge(&self, rev: &str, opts: &MergeOptions) -> Result<Merge> {
+        if self.merge_head()?.is_some() {
+            return Err(Error::Invalid(
+                "a merge is already in progress; finish it with `merge --continue` or drop it with `merge --abort`".to_string(),
+            ));
+        }
+        let theirs = self.find_commit(rev)?;
+
+        // 1) nothing to do, or just move HEAD forward
+        let graph = self.commit_graph()?;
+        let ours = match self.head()? {
+            Some(id) if graph.is_ancestor(&theirs.id, &id) => return Ok(Merge::UpToDate),
+            Some(id) if !graph.is_ancestor(&id, &theirs.id) => self.find_commit(&id)?,
+            _ => {
+                let id = theirs.id.clone();
+                let done = self.checkout_tree(theirs, false)?;
+                self.advance_head(&id, &format!("merge {rev}: Fast-forward"))?;
+                return Ok(Merge::FastForward(done));
+            }
+        };
+        let Some(base) = graph.merge_bases(&ours.id, &[&theirs.id]).into_iter().next() else {
+            return Err(Error::Invalid(format!("refusing to merge unrelated histories ('{rev}' shares no commits with HEAD)")));
+        };
+        let base = self.find_commit(&base)?;
+
+        // 2) start from a clean index: the merge result is staged over HEAD's tree
+        let lock = self.lock_index()?;
+        let mut index = self.load_index()?;
+        if !index.conflicts.is_empty() {
+            return Err(Error::Unmerged(index.conflicts.keys().cloned().collect()));
+        }
+        let (b, o, t) = (base.snapshot(), ours.snapshot(), theirs.snapshot());
+        if index.snapshot() != o {
+            return Err(Error::Invalid("your index has staged changes; commit them before merging".to_string()));
+        }
+
+        // 3) merge every path either side changed
+        let base_label = &base.id[..7];
+        let labels = MergeLabels { ours: "HEAD", base: base_label, theirs: rev };
+        let paths: BTreeSet<&String> = b.keys().chain(o.keys()).chain(t.keys()).collect();
+        let mut results = Vec::new();
+        for path in paths {
+            let (eb, eo, et) = (b.get(path), o.get(path), t.get(path));
+            let resolution = if eo == et || eb == et {
+                continue;
+            } else if eb == eo {
+                Resolution::Clean(et.cloned())
+            } else {
+                self.merge_file(eb, eo, et, labels, opts.style)?
+            };
+            results.push((path.clone(), resolution));
+        }
+
+        // 4) don't clobber local changes (or untracked files) on paths the merge rewrites, or
+        //    in the way of a file that takes a directory's place (or the other way round)
+        let dropped: BTreeSet<&str> = results.iter().filter(|(_, r)| !r.writes_file()).map(|(p, _)| p.as_str()).collect();
+        let mut at_risk = Vec::new();
+        for (path, resolution) in &results {
+            if self.worktree_entry(path, index.get(path))?.as_ref() != o.get(path) {
+                at_risk.push(path.clone());
+            }
+            if resolution.writes_file() {
+                at_risk.extend(self.obstructions(path, |p| dropped.contains(p))?);
+            }
+        }
+        if !at_risk.is_empty() {
+            at_risk.sort();
+            at_risk.dedup();
+            return Err(Error::MergeWouldOverwrite(at_risk));
+        }
+
+        // 5) write the results into the worktree and index, deletions first so a file can
+        //    replace a directory (or the other way round)
+        results.sort_by_key(|(_, r)| r.writes_file());
+        let mut auto_merged = Vec::new();
+        for (path, resolution) in results {
+            let (entry, conflict) = match resolution {
+                Resolution::Clean(entry) => (entry, None),
+                Resolution::Unmergeable { keep, conflict } => (keep, Some(conflict)),
+                Resolution::Content { mode, text, conflict } => {
+                    auto_merged.push(path.clone());
+                    (Some(FileEntry { mode, blob: self.objects().write("blob", &text)? }), conflict)
+                }
+            };
+            let dest = self.worktree().join(&path);
+            match &entry {
+                Some(e) => {
+                    self.make_room_for(&path)?;
+                    self.restore_file(&dest, e)?;
+                }
+                None => self.remove_tracked_file(&dest)?,
+            }
+
+            // a conflicted path keeps our version staged until it's resolved
+            let staged = match &conflict {
+                Some(c) => c.ours.clone(),
+                None => entry,
+            };
+            match staged {
+                Some(e) => {
+                    let stat = match &conflict {
+                        Some(_) => FileStat::default(),
+                        None => FileStat::from_metadata(&fs::symlink_metadata(&dest).with_context(|| format!("reading {path}"))?),
+                    };
+                    index.insert(path.clone(), IndexEntry { blob: e.blob, mode: e.mode, stat });
+                }
+                None => {
+                    index.remove(&path);
+                }
+            }
+            if let Some(c) = conflict {
+                index.conflicts.insert(path, c);
+            }
+        }
+
+        // 6) remember the merge, then commit it unless the user has conflicts to fix
+        let message = match &opts.message {
+            Some(m) => m.clone(),
+            None if self.branch_tip(rev)?.is_some() => format!("Merge branch '{rev}'"),
+            None => format!("Merge commit '{rev}'"),
+        };
+        write_atomic(&self.merge_head_path(), format!("{}\n", theirs.id).as_bytes())?;
+        write_atomic(&self.merge_msg_path(), format!("{message}\n").as_bytes())?;
+        let conflicts: Vec<(String, Conflict)> = index.conflicts.iter().map(|(p, c)| (p.clone(), c.clone())).collect();
+        self.save_index(&index, lock)?;
+        if !conflicts.is_empty() {
+            return Ok(Merge::Conflicted { conflicts, auto_merged });
+        }
+        let commit = self.commit(message)?;
+        Ok(Merge::Merged { commit, auto_merged })
+    }
+
+    /// Three-way merge of one path both sides changed.
+    fn merge_file(
+        &self,
+        base: Option<&FileEntry>,
+        ours: Option<&FileEntry>,
+        theirs: Option<&FileEntry>,
+        labels: MergeLabels,
+        style: ConflictStyle,
+    ) -> Result<Resolution> {
+        let conflict = Conflict { base: base.cloned(), ours: ours.cloned(), theirs: theirs.cloned() };
+        let (Some(o), Some(t)) = (ours, theirs) else {
+            // modified on one side, deleted on the other: keep the modified one to look at
+            return Ok(Resolution::Unmergeable { keep: ours.or(theirs).cloned(), conflict });
+        };
+        let read = |e: Option<&FileEntry>| e.map_or(Ok(Vec::new()), |e| self.objects().read_blob(&e.blob));
+        let (db, dour, dt) = (read(base)?, read(ours)?, read(theirs)?);
+        let symlink = [base, ours, theirs].iter().flatten().any(|e| e.mode == MODE_SYMLINK);
+        if symlink || is_binary(&db) || is_binary(&dour) || is_binary(&dt) {
+            return Ok(Resolution::Unmergeable { keep: Some(o.clone()), conflict });
+        }
+        // a mode change on one side wins like a content change does
+        let mode = if base.is_some_and(|b| b.mode == o.mode) { t.mode } else { o.mode };
+        let merged = merge3(&db, &dour, &dt, labels, style);
+        let conflict = (merged.conflicts > 0).then_some(conflict);
+        Ok(Resolution::Content { mode, text: merged.text, conflict })
+    }
+
+    /// Finish a merge that stopped for conflicts: commit the index (which must have no
+    /// unresolved paths left) with both sides as parents.
+    pub fn merge_continue(&self, message: Option<String>) -> Result<Commit> {
+        if self.merge_head()?.is_none() {
+            return Err(Error::Invalid("there is no merge to continue".to_string()));
+        }
+        let message = match message {
+            Some(m) => m,
+            None => {
+                let path = self.merge_msg_path();
+                let text = fs::read_to_string(&path).with_context(|| format!("reading {}", path.display()))?;
+                text.trim_end().to_string()
+            }
+        };
+        self.commit(message)
+    }
+
+    /// Abandon a merge that stopped for conflicts: put back HEAD's version of every path the
+    /// merge left unresolved or staged (resolutions included). Local edits to other paths
+    /// stay, since the merge started from a clean index and left edited files alone.
+    pub fn merge_abort(&self) -> Result<Checkout> {
+        if self.merge_head()?.is_none() {
+            return Err(Error::Invalid("there is no merge to abort".to_string()));
+        }
+        let head = self.find_commit("HEAD")?;
+        let lock = self.lock_index()?;
+        let mut index = self.load_index()?;
+
+        // 1) the merge's paths: unresolved ones, and any staged differently from HEAD
+        let (files, staged) = (head.snapshot(), index.snapshot());
+        let mut paths: BTreeSet<String> = index.conflicts.keys().cloned().collect();
+        paths.extend(files.keys().chain(staged.keys()).filter(|p| files.get(*p) != staged.get(*p)).cloned());
+
+        // 2) HEAD's version of each goes back into the worktree and index (deletions first)
+        let mut paths: Vec<String> = paths.into_iter().collect();
+        paths.sort_by_key(|p| files.contains_key(p));
+        let (mut restored, mut removed) = (Vec::new(), Vec::new());
+        for path in paths {
+            let dest = self.worktree().join(&path);
+            match files.get(&path) {
+                Some(e) => {
+                    self.make_room_for(&path)?;
+                    self.restore_file(&dest, e)?;
+                    let stat = FileStat::from_metadata(&fs::symlink_metadata(&dest).with_context(|| format!("reading {path}"))?);
+                    index.insert(path.clone(), IndexEntry { blob: e.blob.clone(), mode: e.mode, stat });
+                    restored.push(path);
+                }
+                None => {
+                    index.remove(&path);
+                    if fs::symlink_metadata(&dest).is_ok() {
+                        self.remove_tracked_file(&dest)?;
+                        removed.push(path);
+                    }
+                }
+            }
+        }
+        index.conflicts.clear();
+        self.save_index(&index, lock)?;
+        self.clear_merge_state()?;
+        Ok(Checkout { commit: head, restored, removed })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stage::AddOptions;
+    use crate::testing::{commit, read, scratch, write};
+
+    #[test]
+    fn abort_keeps_unrelated_local_edits() {
+        let repo = scratch("merge-abort");
+        commit(&repo, &[("conflicted", "base\n"), ("clean", "base\n"), ("edited", "base\n")], &[], "base");
+        repo.create_branch("other", None, false).unwrap();
+        commit(&repo, &[("conflicted", "ours\n")], &[], "ours");
+        repo.switch("other", false).unwrap();
+        commit(&repo, &[("conflicted", "theirs\n"), ("clean", "theirs\n"), ("added", "theirs\n")], &[], "theirs");
+        repo.switch("main", false).unwrap();
+
+        write(&repo, "edited", "local\n");
+        let merged = repo.merge("other", &MergeOptions::default()).unwrap();
+        assert!(matches!(merged, Merge::Conflicted { .. }), "{merged:?}");
+        repo.merge_abort().unwrap();
+
+        // everything the merge wrote is back to HEAD; the local edit is untouched
+        assert_eq!(read(&repo, "conflicted"), "ours\n");
+        assert_eq!(read(&repo, "clean"), "base\n");
+        assert!(!repo.worktree().join("added").exists());
+        assert_eq!(read(&repo, "edited"), "local\n");
+        assert_eq!(repo.merge_head().unwrap(), None);
+        let st = repo.status().unwrap();
+        assert_eq!(st.unstaged_modified, ["edited"]);
+        assert!(st.unmerged.is_empty() && st.staged_new.is_empty() && st.staged_modified.is_empty());
+        fs::remove_dir_all(repo.worktree()).unwrap();
+    }
+
+    #[test]
+    fn rm_resolves_a_path_deleted_by_us() {
+        let repo = scratch("merge-rm");
+        commit(&repo, &[("gone", "base\n"), ("kept", "base\n")], &[], "base");
+        repo.create_branch("other", None, false).unwrap();
+        repo.rm(&["gone".into()], false, false, false).unwrap();
+        repo.commit("delete".to_string()).unwrap();
+        repo.switch("other", false).unwrap();
+        commit(&repo, &[("gone", "theirs\n")], &[], "edit");
+        repo.switch("main", false).unwrap();
+
+        repo.merge("other", &MergeOptions::default()).unwrap();
+        assert_eq!(repo.status().unwrap().unmerged[0].1.code(), "DU");
+        assert_eq!(repo.rm(&["gone".into()], false, false, false).unwrap(), ["gone"]);
+        assert!(!repo.worktree().join("gone").exists());
+        let commit = repo.merge_continue(None).unwrap();
+        assert!(!commit.snapshot().contains_key("gone"));
+        fs::remove_dir_all(repo.worktree()).unwrap();
+    }
+
+    #[test]
+    fn merge_replaces_a_directory_with_a_file() {
+        let repo = scratch("merge-dir-to-file");
+        commit(&repo, &[("a/b", "nested\n"), ("other", "base\n")], &[], "base");
+        repo.create_branch("other", None, false).unwrap();
+        commit(&repo, &[("other", "ours\n")], &[], "ours");
+        repo.switch("other", false).unwrap();
+        commit(&repo, &[("a", "file\n"), ("other", "theirs\n")], &["a/b"], "theirs");
+        repo.switch("main", false).unwrap();
+
+        // the file takes the directory's place, and an abort puts the directory back
+        let merged = repo.merge("other", &MergeOptions::default()).unwrap();
+        assert!(matches!(merged, Merge::Conflicted { .. }), "{merged:?}");
+        assert_eq!(read(&repo, "a"), "file\n");
+        repo.merge_abort().unwrap();
+        assert_eq!(read(&repo, "a/b"), "nested\n");
+        assert!(repo.status().unwrap().is_clean());
+
+        repo.merge("other", &MergeOptions::default()).unwrap();
+        write(&repo, "other", "both\n");
+        repo.add(&["other".into()], AddOptions::default()).unwrap();
+        let commit = repo.merge_continue(None).unwrap();
+        assert_eq!(commit.tree.keys().collect::<Vec<_>>(), ["a", "other"]);
+        assert_eq!(read(&repo, "a"), "file\n");
+        assert!(repo.status().unwrap().is_clean());
+        fs::remove_dir_all(repo.worktree()).unwrap();
+    }
+}
@@ -0,0 +1,398 @@
+//! Content-addressed object storage: blobs, git trees and commits, zlib-compressed under
+//! `.minigit/objects/ab/cdef...` and named by the repository's hash of their content.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+
+use crate::commit::ident_line;
+use crate::error::{Context, Error, Result};
+use crate::index::{FileEntry, Snapshot};
+use crate::util::write_atomic;
+
+/* -------- hashing -------- */
+
+/// Which hash names objects. Chosen once at `init`; ids from different formats never mix.
+#[derive(Serialize, Deserialize, Default, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ObjectFormat {
+    #[default]
+    Sha1,
+    Sha256,
+    Blake3,
+}
+
+impl ObjectFormat {
+    pub fn name(self) -> &'static str {
+        match self {
+            ObjectFormat::Sha1 => "sha1",
+            ObjectFormat::Sha256 => "sha256",
+            ObjectFormat::Blake3 => "blake3",
+        }
+    }
+
+    /// Length of an id in hex characters.
+    pub fn hex_len(self) -> usize {
+        match self {
+            ObjectFormat::Sha1 => 40,
+            ObjectFormat::Sha256 | ObjectFormat::Blake3 => 64,
+        }
+    }
+
+    pub fn hash_hex(self, bytes: &[u8]) -> String {
+        match self {
+            ObjectFormat::Sha1 => sha1_hex(bytes),
+            ObjectFormat::Sha256 => to_hex(&Sha256::digest(bytes)),
+            ObjectFormat::Blake3 => to_hex(blake3::hash(bytes).as_bytes()),
+        }
+    }
+
+    /// Is `s` a full id in this format?
+    pub fn is_id(self, s: &str) -> bool {
+        s.len() == self.hex_len() && s.bytes().all(|b| b.is_ascii_hexdigit())
+    }
+}
+
+impl std::str::FromStr for ObjectFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sha1" => Ok(ObjectFormat::Sha1),
+            "sha256" => Ok(ObjectFormat::Sha256),
+            "blake3" => Ok(ObjectFormat::Blake3),
+            _ => Err(format!("unknown object format {s:?} (expected sha1, sha256 or blake3)")),
+        }
+    }
+}
+
+// Turn any input bytes into a lowercase SHA-1 hex string (40 chars).
+pub fn sha1_hex(bytes: impl AsRef<[u8]>) -> String {
+    // 1) Make a new SHA-1 hasher.
+    let mut h = Sha1::new();
+
+    // 2) Feed the input (as bytes) into the hasher. (No copies; as_ref() borrows.)
+    h.update(bytes.as_ref());
+
+    // 3) Finish the hash: get 20 raw bytes (not text!).
+    let out = h.finalize(); // e.g. [0xaa, 0xf4, 0xc6, …] for "hello"
+
+    // 4) Spell the bytes out as 40 hex chars.
+    to_hex(&out)
+}
+
+// Turn raw digest bytes into lowercase hex (2 chars per byte).
+pub fn to_hex(out: &[u8]) -> String {
+    // 1) A tiny lookup table: 0..15 → '0'..'f' (hex digits).
+    const T: &[u8; 16] = b"0123456789abcdef";
+
+    // 2) Pre-allocate space (2 hex chars per byte).
+    let mut s = String::with_capacity(out.len() * 2);
+
+    // 3) For each byte, split into two 4-bit numbers (nibbles) and map to hex.
+    for &b in out {
+        // high nibble: top 4 bits → index 0..15 → hex char
+        s.push(T[(b >> 4) as usize] as char);
+        // low nibble: bottom 4 bits → index 0..15 → hex char
+        s.push(T[(b & 0x0f) as usize] as char);
+    }
+
+    // 4) Return the hex string.
+    s
+}
+
+/// Parse a hex id into its raw bytes (git trees store ids in binary).
+pub fn hex_to_bytes(hex: &str) -> Result<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return Err(Error::InvalidObjectId(hex.to_string()));
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| Error::InvalidObjectId(hex.to_string())))
+        .collect()
+}
+
+/* -------- encoding -------- */
+
+/// Object types that may appear in an object header.
+pub const OBJECT_KINDS: &[&str] = &["blob", "tree", "commit", "tag"];
+
+/// Serialize an object the way git does on disk: zlib("<kind> <len>\0<data>").
+pub fn encode_object(kind: &str, data: &[u8]) -> Vec<u8> {
+    use std::io::Write;
+    // writing into a Vec can't fail
+    let mut z = ZlibEncoder::new(Vec::new(), Compression::default());
+    let _ = z.write_all(format!("{kind} {}\0", data.len()).as_bytes());
+    let _ = z.write_all(data);
+    z.finish().unwrap_or_default()
+}
+
+/// Inverse of `encode_object`. None means "not a compressed object" (e.g., a legacy raw blob).
+pub fn decode_object(raw: &[u8]) -> Option<(String, Vec<u8>)> {
+    use std::io::Read;
+    let mut bytes = Vec::new();
+    ZlibDecoder::new(raw).read_to_end(&mut bytes).ok()?;
+    let nul = bytes.iter().position(|&b| b == 0)?;
+    let header = std::str::from_utf8(&bytes[..nul]).ok()?;
+    let (kind, len) = header.split_once(' ')?;
+    let len: usize = len.parse().ok()?;
+    if !OBJECT_KINDS.contains(&kind) || bytes.len() - nul - 1 != len {
+        return None;
+    }
+    Some((kind.to_string(), bytes.split_off(nul + 1)))
+}
+
+/* -------- store -------- */
+
+/// The `objects/` directory of one repository, with the hashing rules chosen at `init`.
+#[derive(Clone, Debug)]
+pub struct ObjectStore {
+    dir: PathBuf,
+    format: ObjectFormat,
+    git_compatible: bool,
+}
+
+impl ObjectStore {
+    pub fn new(dir: impl Into<PathBuf>, format: ObjectFormat, git_compatible: bool) -> Self {
+        ObjectStore { dir: dir.into(), format, git_compatible }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn format(&self) -> ObjectFormat {
+        self.format
+    }
+
+    /// Are ids and objects byte-for-byte what git would produce?
+    pub fn is_git_compatible(&self) -> bool {
+        self.git_compatible
+    }
+
+    /// Reject ids that belong to a different object format (e.g., a SHA-1 id in a SHA-256 repo).
+    pub fn check_id(&self, id: &str) -> Result<()> {
+        if self.format.is_id(id) {
+            return Ok(());
+        }
+        if id.bytes().all(|b| b.is_ascii_hexdigit()) && (id.len() == 40 || id.len() == 64) {
+            return Err(Error::ObjectFormatMismatch { id: id.to_string(), format: self.format });
+        }
+        Err(Error::InvalidObjectId(id.to_string()))
+    }
+
+    /// Where an object lives: `objects/ab/cdef...`, sharded by the first two hex digits like git.
+    pub fn path(&self, id: &str) -> PathBuf {
+        let (dir, rest) = id.split_at(2.min(id.len()));
+        self.dir.join(dir).join(rest)
+    }
+
+    /// Where older repositories kept objects: directly in `objects/`.
+    pub fn flat_path(&self, id: &str) -> PathBuf {
+        self.dir.join(id)
+    }
+
+    /// The object's file in whichever layout has it.
+    fn find_file(&self, id: &str) -> Option<PathBuf> {
+        [self.path(id), self.flat_path(id)].into_iter().find(|p| p.is_file())
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.find_file(id).is_some()
+    }
+
+    /// Every object file in either layout: (id, path).
+    pub fn list(&self) -> Result<Vec<(String, PathBuf)>> {
+        let read_dir = |dir: &Path| fs::read_dir(dir).with_context(|| format!("reading {}", dir.display()));
+        let mut out = Vec::new();
+        for entry in read_dir(&self.dir)? {
+            let path = entry.with_context(|| format!("reading {}", self.dir.display()))?.path();
+            let name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+            if path.is_dir() && name.len() == 2 {
+                for inner in read_dir(&path)? {
+                    let inner = inner.with_context(|| format!("reading {}", path.display()))?.path();
+                    let id = format!("{name}{}", inner.file_name().unwrap_or_default().to_string_lossy());
+                    if self.format.is_id(&id) {
+                        out.push((id, inner));
+                    }
+                }
+            } else if self.format.is_id(&name) {
+                out.push((name, path));
+            }
+        }
+        out.sort();
+        Ok(out)
+    }
+
+    /// An object's id: the repo's hash of the bare content, or in git-compatible repos of
+    /// `"<kind> <len>\0<content>"` (what `git hash-object` prints).
+    pub fn hash(&self, kind: &str, data: &[u8]) -> String {
+        if self.git_compatible {
+            let mut full = format!("{kind} {}\0", data.len()).into_bytes();
+            full.extend_from_slice(data);
+            self.format.hash_hex(&full)
+        } else {
+            self.format.hash_hex(data)
+        }
+    }
+
+    /// Store `data` as a compressed object and return its id (see `hash`).
+    /// Existing objects are left alone: same id ⇒ same content.
+    pub fn write(&self, kind: &str, data: &[u8]) -> Result<String> {
+        let id = self.hash(kind, data);
+        if !self.contains(&id) {
+            let obj = self.path(&id);
+            if let Some(dir) = obj.parent() {
+                fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+            }
+            write_atomic(&obj, &encode_object(kind, data))?;
+        }
+        Ok(id)
+    }
+
+    /// Read an object back as (kind, bytes). Objects written before compression was
+    /// introduced are raw file bytes and are returned as blobs.
+    pub fn read(&self, id: &str) -> Result<(String, Vec<u8>)> {
+        self.check_id(id)?;
+        let obj = self.find_file(id).ok_or_else(|| Error::ObjectNotFound(id.to_string()))?;
+        let raw = fs::read(&obj).with_context(|| format!("reading object {id}"))?;
+        Ok(decode_object(&raw).unwrap_or_else(|| ("blob".to_string(), raw)))
+    }
+
+    /// Read a blob's bytes back out of the store.
+    pub fn read_blob(&self, id: &str) -> Result<Vec<u8>> {
+        let (kind, data) = self.read(id)?;
+        if kind != "blob" {
+            return Err(Error::WrongObjectKind { id: id.to_string(), kind, expected: "blob" });
+        }
+        Ok(data)
+    }
+
+    /// Write git tree objects for a flat snapshot (one per directory); returns the root tree id.
+    pub fn write_git_tree(&self, files: &Snapshot) -> Result<String> {
+        // 1) split into this directory's files and per-subdirectory maps
+        let mut blobs: Vec<(&str, &FileEntry)> = Vec::new();
+        let mut dirs: BTreeMap<&str, Snapshot> = BTreeMap::new();
+        for (path, entry) in files {
+            match path.split_once('/') {
+                Some((dir, rest)) => {
+                    dirs.entry(dir).or_default().insert(rest.to_string(), entry.clone());
+                }
+                None => blobs.push((path, entry)),
+            }
+        }
+
+        // 2) git sorts entries by name, comparing directories as if they ended in '/'
+        let mut entries: Vec<(String, String, &str, String)> = Vec::new(); // (sort key, mode, name, id)
+        for (name, entry) in blobs {
+            entries.push((name.to_string(), format!("{:o}", entry.mode), name, entry.blob.clone()));
+        }
+        for (name, sub) in dirs {
+            entries.push((format!("{name}/"), "40000".to_string(), name, self.write_git_tree(&sub)?));
+        }
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+        // 3) "<mode> <name>\0<binary id>" per entry
+        let mut data = Vec::new();
+        for (_, mode, name, id) in entries {
+            data.extend_from_slice(format!("{mode} {name}\0").as_bytes());
+            data.extend_from_slice(&hex_to_bytes(&id)?);
+        }
+        self.write("tree", &data)
+    }
+
+    /// Write a git commit object (and its trees) and return its id.
+    pub fn write_git_commit(
+        &self,
+        parents: &[String],
+        author: &str,
+        timestamp: &str,
+        message: &str,
+        tree: &Snapshot,
+    ) -> Result<String> {
+        let when = chrono::DateTime::parse_from_rfc3339(timestamp)
+            .map_err(|e| Error::Invalid(format!("bad timestamp {timestamp}: {e}")))?;
+        let ident = ident_line(author, &when);
+        let mut text = format!("tree {}\n", self.write_git_tree(tree)?);
+        for p in parents {
+            text.push_str(&format!("parent {p}\n"));
+        }
+        text.push_str(&format!("author {ident}\ncommitter {ident}\n\n{message}"));
+        if !text.ends_with('\n') {
+            text.push('\n');
+        }
+        self.write("commit", text.as_bytes())
+    }
+
+    /// Rewrite raw (pre-compression) objects in the compressed, typed format; returns their ids.
+    pub fn compress_legacy(&self) -> Result<Vec<String>> {
+        let mut migrated = Vec::new();
+        for (id, path) in self.list()? {
+            let raw = fs::read(&path).with_context(|| format!("reading {}", path.display()))?;
+            if decode_object(&raw).is_some() {
+                continue;
+            }
+            let actual = self.hash("blob", &raw);
+            if actual != id {
+                return Err(Error::Corrupt {
+                    what: format!("object {id}"),
+                    reason: format!("its content hashes to {actual}"),
+                });
+            }
+            // a crash mid-rewrite leaves the old raw object, never a half-written one
+            write_atomic(&path, &encode_object("blob", &raw))?;
+            migrated.push(id);
+        }
+        Ok(migrated)
+    }
+
+    /// Move an object found at `flat_path` into its `objects/ab/` shard (dropping it if
+    /// the shard already has a copy).
+    pub fn move_to_fanout(&self, id: &str) -> Result<()> {
+        let (from, dest) = (self.flat_path(id), self.path(id));
+        if dest.exists() {
+            fs::remove_file(&from).with_context(|| format!("removing duplicate {}", from.display()))
+        } else {
+            let shard = dest.parent().unwrap_or(&self.dir);
+            fs::create_dir_all(shard).with_context(|| format!("creating {}", shard.display()))?;
+            fs::rename(&from, &dest).with_context(|| format!("moving {}", from.display()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::{MODE_EXEC, MODE_FILE};
+
+    // ids below are what real git 2.x prints for the same content
+    #[test]
+    fn git_compatible_blob_ids_match_git() {
+        let store = ObjectStore::new(std::env::temp_dir(), ObjectFormat::Sha1, true);
+        assert_eq!(store.hash("blob", b"hello\n"), "ce013625030ba8dba906f756967f9e9ca394464a");
+        assert_eq!(store.hash("blob", b""), "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391");
+    }
+
+    #[test]
+    fn git_compatible_tree_and_commit_ids_match_git() {
+        let dir = std::env::temp_dir().join(format!("mini-git-objects-{}", std::process::id()));
+        let store = ObjectStore::new(&dir, ObjectFormat::Sha1, true);
+        let hello = store.write("blob", b"hello\n").unwrap();
+        let main = store.write("blob", b"fn main() {}\n").unwrap();
+        let files = Snapshot::from([
+            ("hello.txt".to_string(), FileEntry { mode: MODE_EXEC, blob: hello }),
+            ("src/main.rs".to_string(), FileEntry { mode: MODE_FILE, blob: main }),
+        ]);
+        // `git write-tree` after adding the same files (hello.txt executable)
+        assert_eq!(store.write_git_tree(&files).unwrap(), "7073df977fe8b01622bf98641d4db9dcb8102fc6");
+        // `git commit -m first` with author and committer "Ada <ada@example.com>" at that time
+        let commit = store.write_git_commit(&[], "Ada <ada@example.com>", "2024-01-02T03:04:05+01:00", "first", &files);
+        assert_eq!(commit.unwrap(), "821c110080102e48915826ea0acb1f441f9a6849");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
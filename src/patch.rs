@@ -0,0 +1,230 @@
+//! Staging part of a file's changes (`add -p`): the diff from a file's staged blob to the
+//! working tree, cut into hunks the caller can split, hand-edit and stage one by one.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::ops::Range;
+use std::path::PathBuf;
+
+use crate::diff::{self, Op};
+use crate::error::{Context, Result};
+use crate::index::{FileStat, Index, IndexEntry, MODE_SYMLINK};
+use crate::repository::Repository;
+use crate::stage::{is_pathspec_glob, pathspec_matches};
+use crate::worktree::read_worktree_file;
+
+/// One line of a file being staged hunk by hunk; `staged` matters only for changes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PatchLine {
+    pub op: Op,
+    pub text: Vec<u8>,
+    pub staged: bool,
+}
+
+/// A tracked file's unstaged changes, as hunks of [`PatchLine`]s.
+#[derive(Clone, Debug)]
+pub struct FilePatch {
+    pub path: String,
+    /// The staged mode, which the partially staged blob keeps.
+    pub mode: u32,
+    /// A symlink or binary file, which can only be staged whole (`lines` is empty).
+    pub binary: bool,
+    /// Every line from the staged blob to the worktree file, changes unstaged at first.
+    pub lines: Vec<PatchLine>,
+    /// Ranges of `lines`, each a run of changes with up to 3 lines of context.
+    pub hunks: Vec<Range<usize>>,
+}
+
+impl FilePatch {
+    fn ops(&self) -> Vec<Op> {
+        self.lines.iter().map(|l| l.op).collect()
+    }
+
+    /// The lines of hunk `i`.
+    pub fn hunk(&self, i: usize) -> &[PatchLine] {
+        &self.lines[self.hunks[i].clone()]
+    }
+
+    /// Hunk `i`'s `@@ -a,b +c,d @@` line.
+    pub fn hunk_header(&self, i: usize) -> String {
+        diff::hunk_header(&self.ops(), self.hunks[i].clone())
+    }
+
+    /// Mark every change in hunk `i` for staging.
+    pub fn stage_hunk(&mut self, i: usize) {
+        let range = self.hunks[i].clone();
+        self.lines[range].iter_mut().for_each(|l| l.staged = true);
+    }
+
+    /// Hunk `i` cut at the unchanged lines between its runs of changes; each piece keeps up
+    /// to 3 lines of context on either side (shared context is harmless: only changes get staged).
+    fn pieces(&self, i: usize) -> Vec<Range<usize>> {
+        let range = self.hunks[i].clone();
+        let ops: Vec<Op> = self.lines[range.clone()].iter().map(|l| l.op).collect();
+        let runs = diff::hunk_ranges(&ops, 0);
+        let mut out = Vec::new();
+        for (j, run) in runs.iter().enumerate() {
+            let lo = if j == 0 { 0 } else { runs[j - 1].end };
+            let hi = runs.get(j + 1).map_or(ops.len(), |next| next.start);
+            out.push(range.start + run.start.saturating_sub(3).max(lo)..range.start + (run.end + 3).min(hi));
+        }
+        out
+    }
+
+    /// Whether hunk `i` has more than one run of changes to split it into.
+    pub fn can_split(&self, i: usize) -> bool {
+        self.pieces(i).len() > 1
+    }
+
+    /// Replace hunk `i` with its pieces (see [`FilePatch::can_split`]); returns how many.
+    pub fn split_hunk(&mut self, i: usize) -> usize {
+        let pieces = self.pieces(i);
+        let n = pieces.len();
+        self.hunks.splice(i..=i, pieces);
+        n
+    }
+
+    /// Hunk `i` as patch text for hand-editing: ` `, `-` or `+` lines, with git's marker
+    /// after a final line that has no newline.
+    pub fn hunk_text(&self, i: usize) -> Vec<u8> {
+        let mut text = Vec::new();
+        for l in self.hunk(i) {
+            text.push(match l.op {
+                Op::Equal => b' ',
+                Op::Delete => b'-',
+                Op::Insert => b'+',
+            });
+            text.extend_from_slice(&l.text);
+            if !l.text.ends_with(b"\n") {
+                text.extend_from_slice(b"\n\\ No newline at end of file\n");
+            }
+        }
+        text
+    }
+
+    /// Replace hunk `i` with a hand-edited version of its [`FilePatch::hunk_text`], every
+    /// change in it staged. `#` comments and `@@` lines are ignored. Returns false (and
+    /// changes nothing) if the edit doesn't parse or no longer applies to the old side.
+    pub fn edit_hunk(&mut self, i: usize, edited: &[u8]) -> bool {
+        // 1) parse: anything but comments, the @@ line and ` `/`-`/`+` lines is invalid
+        let mut out: Vec<PatchLine> = Vec::new();
+        for line in diff::split_lines(edited) {
+            let (op, rest) = match line.split_first() {
+                Some((b'#', _)) => continue,
+                Some((b'@', _)) if line.starts_with(b"@@") => continue,
+                Some((b'\\', _)) => {
+                    // "\ No newline at end of file" belongs to the line before it
+                    if let Some(prev) = out.last_mut()
+                        && prev.text.ends_with(b"\n")
+                    {
+                        prev.text.pop();
+                    }
+                    continue;
+                }
+                Some((b' ', rest)) => (Op::Equal, rest),
+                Some((b'-', rest)) => (Op::Delete, rest),
+                Some((b'+', rest)) => (Op::Insert, rest),
+                Some((b'\n', _)) => (Op::Equal, line), // an emptied context line
+                _ => return false,
+            };
+            out.push(PatchLine { op, text: rest.to_vec(), staged: true });
+        }
+
+        // 2) the old side must be untouched, or the edit can't be applied to the index version
+        let old_side = |ls: &[PatchLine]| -> Vec<Vec<u8>> {
+            ls.iter().filter(|l| l.op != Op::Insert).map(|l| l.text.clone()).collect()
+        };
+        if old_side(&out) != old_side(self.hunk(i)) {
+            return false;
+        }
+
+        // 3) later hunks shift by the size change; context they shared with this one is gone
+        let range = self.hunks[i].clone();
+        let (start, len) = (range.start, out.len());
+        let delta = len as isize - range.len() as isize;
+        self.lines.splice(range, out);
+        self.hunks[i] = start..start + len;
+        for h in &mut self.hunks[i + 1..] {
+            *h = ((h.start as isize + delta) as usize).max(start + len)..(h.end as isize + delta) as usize;
+        }
+        true
+    }
+
+    /// Whether any change has been picked.
+    pub fn has_staged(&self) -> bool {
+        self.lines.iter().any(|l| l.staged && l.op != Op::Equal)
+    }
+
+    /// The new index version: old lines, with only the staged changes applied.
+    pub fn staged_content(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        for l in &self.lines {
+            let keep = match l.op {
+                Op::Equal => true,
+                Op::Delete => !l.staged,
+                Op::Insert => l.staged,
+            };
+            if keep {
+                out.extend_from_slice(&l.text);
+            }
+        }
+        out
+    }
+}
+
+impl Repository {
+    /// The unstaged changes of tracked files under `pathspecs` (all of them when empty),
+    /// in path order. Deleted files are left out: only `add -u` or `rm` can stage those.
+    pub fn file_patches(&self, index: &Index, pathspecs: &[PathBuf]) -> Result<Vec<FilePatch>> {
+        // 1) tracked files in the pathspec
+        let mut rels = BTreeSet::new();
+        if pathspecs.is_empty() {
+            rels.extend(index.keys().cloned());
+        }
+        for p in pathspecs {
+            let rel = self.to_repo_relative(p)?;
+            if is_pathspec_glob(p) {
+                rels.extend(index.keys().filter(|f| pathspec_matches(&rel, f)).cloned());
+            } else {
+                rels.extend(index.tracked_under(&rel).cloned());
+            }
+        }
+
+        // 2) diff each changed one against its staged blob
+        let mut out = Vec::new();
+        for rel in rels {
+            let entry = &index[&rel];
+            let Some(work) = self.worktree_entry(&rel, Some(entry))? else { continue };
+            if work.blob == entry.blob {
+                continue;
+            }
+            let old = self.objects().read_blob(&entry.blob)?;
+            let path = self.worktree().join(&rel);
+            let meta = fs::symlink_metadata(&path).with_context(|| format!("reading {}", path.display()))?;
+            let new = read_worktree_file(&path, &meta)?;
+            let mut patch = FilePatch { path: rel, mode: entry.mode, binary: true, lines: Vec::new(), hunks: Vec::new() };
+            if entry.mode != MODE_SYMLINK && work.mode != MODE_SYMLINK && !diff::is_binary(&old) && !diff::is_binary(&new) {
+                let (a, b) = (diff::split_lines(&old), diff::split_lines(&new));
+                patch.binary = false;
+                patch.lines = diff::diff_lines(&a, &b, &diff::Options::default())
+                    .iter()
+                    .map(|e| PatchLine { op: e.op, text: e.text(&a, &b).to_vec(), staged: false })
+                    .collect();
+                patch.hunks = diff::hunk_ranges(&patch.ops(), 3);
+            }
+            out.push(patch);
+        }
+        Ok(out)
+    }
+
+    /// Stage the picked changes of `patch` in `index` as a new blob; returns whether there
+    /// were any. The zeroed stat data makes `status` re-hash the file.
+    pub fn stage_partial(&self, index: &mut Index, patch: &FilePatch) -> Result<bool> {
+        if !patch.has_staged() {
+            return Ok(false);
+        }
+        let blob = self.objects().write("blob", &patch.staged_content())?;
+        index.insert(patch.path.clone(), IndexEntry { blob, mode: patch.mode, stat: FileStat::default() });
+        Ok(true)
+    }
+}
@@ -0,0 +1,472 @@
+//! Branches, tags and HEAD. A branch is a file `.minigit/refs/heads/<name>` holding a
+//! commit id; HEAD names the current branch (`ref: refs/heads/main`), or holds a commit id
+//! itself when detached (as in repositories from before branches existed). A tag is a file
+//! `.minigit/refs/tags/<name>` holding a commit id, or the id of an annotated [`Tag`] object.
+//! Every move of HEAD or a branch is appended to its reflog under `.minigit/logs/`.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::commit::{author_ident, ident_line, parse_ident_line};
+use crate::error::{Context, Error, Result};
+use crate::repository::Repository;
+use crate::tag::Tag;
+use crate::util::write_atomic;
+use crate::worktree::Checkout;
+
+/// The branch a new repository starts on.
+pub const DEFAULT_BRANCH: &str = "main";
+
+/// What HEAD points at.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Head {
+    /// On a branch; it has no commits yet ("unborn") if its ref file doesn't exist.
+    Branch(String),
+    /// Detached at a commit.
+    Detached(String),
+}
+
+/// One reflog line: a ref moving from `old` (None when it was created) to `new`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReflogEntry {
+    pub old: Option<String>,
+    pub new: String,
+    pub who: String,
+    /// RFC 3339 timestamp with the local offset.
+    pub timestamp: String,
+    pub message: String,
+}
+
+/// Reject names git wouldn't accept under `refs/` (a subset of `git check-ref-format`);
+/// `kind` ("branch" or "tag") goes in the error.
+pub fn check_ref_name(name: &str, kind: &'static str) -> Result<()> {
+    let bad_char = |c: char| c.is_ascii_control() || " ~^:?*[\\".contains(c);
+    let ok = !name.is_empty()
+        && name != "HEAD"
+        && name != "@"
+        && !name.starts_with('-')
+        && !name.contains(bad_char)
+        && !name.contains("..")
+        && !name.contains("@{")
+        && !name.ends_with(".lock")
+        && !name.ends_with('.')
+        && name.split('/').all(|part| !part.is_empty() && !part.starts_with('.'));
+    if ok { Ok(()) } else { Err(Error::InvalidRefName { kind, name: name.to_string() }) }
+}
+
+impl Repository {
+    fn head_path(&self) -> PathBuf {
+        self.git_dir().join("HEAD")
+    }
+
+    fn heads_dir(&self) -> PathBuf {
+        self.git_dir().join("refs").join("heads")
+    }
+
+    fn branch_path(&self, name: &str) -> PathBuf {
+        self.heads_dir().join(name)
+    }
+
+    fn tags_dir(&self) -> PathBuf {
+        self.git_dir().join("refs").join("tags")
+    }
+
+    /// Read the id stored in ref file `path` (None if there's no such file).
+    pub(crate) fn read_ref(&self, path: &Path, what: impl FnOnce() -> String) -> Result<Option<String>> {
+        match fs::read_to_string(path) {
+            Ok(text) => {
+                let id = text.trim();
+                self.objects().check_id(id).map_err(|err| Error::Corrupt { what: what(), reason: err.to_string() })?;
+                Ok(Some(id.to_string()))
+            }
+            Err(e) if matches!(e.kind(), std::io::ErrorKind::NotFound | std::io::ErrorKind::IsADirectory) => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("reading {}", path.display())),
+        }
+    }
+
+    /// Write `id` to a ref file, creating directories for names with `/` in them.
+    fn write_ref(&self, path: &Path, id: &str) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+        }
+        write_atomic(path, format!("{id}\n").as_bytes())
+    }
+
+    /// Remove a ref file under `root`, then any directories below `root` it leaves empty.
+    fn remove_ref(&self, root: &Path, path: &Path) -> Result<()> {
+        fs::remove_file(path).with_context(|| format!("removing {}", path.display()))?;
+        let mut dir = path.parent();
+        while let Some(d) = dir {
+            if d == root || fs::remove_dir(d).is_err() {
+                break;
+            }
+            dir = d.parent();
+        }
+        Ok(())
+    }
+
+    /// Every ref name under `root`, sorted; names may contain `/`.
+    fn ref_names(&self, root: &Path) -> Result<Vec<String>> {
+        fn collect(dir: &Path, prefix: &str, out: &mut Vec<String>) -> Result<()> {
+            let entries = match fs::read_dir(dir) {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+                Err(e) => return Err(e).with_context(|| format!("reading {}", dir.display())),
+            };
+            for entry in entries {
+                let entry = entry.with_context(|| format!("reading {}", dir.display()))?;
+                let name = format!("{prefix}{}", entry.file_name().to_string_lossy());
+                if entry.path().is_dir() {
+                    collect(&entry.path(), &format!("{name}/"), out)?;
+                } else if !name.contains(".tmp-") {
+                    out.push(name);
+                }
+            }
+            Ok(())
+        }
+        let mut names = Vec::new();
+        collect(root, "", &mut names)?;
+        names.sort();
+        Ok(names)
+    }
+
+    /* -------- reflogs -------- */
+
+    fn reflog_path(&self, refname: &str) -> PathBuf {
+        self.git_dir().join("logs").join(refname)
+    }
+
+    /// Append a line to `refname`'s reflog, in git's format:
+    /// `<old> <new> <ident> <time> <zone>\t<message>` (an all-zero `old` for a new ref).
+    fn append_reflog(&self, refname: &str, old: Option<&str>, new: &str, message: &str) -> Result<()> {
+        use std::io::Write;
+        let path = self.reflog_path(refname);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+        }
+        let zero = "0".repeat(self.objects().format().hex_len());
+        let who = ident_line(&author_ident(), &chrono::Local::now().fixed_offset());
+        let line = format!("{} {new} {who}\t{}\n", old.unwrap_or(&zero), message.lines().next().unwrap_or(""));
+        let mut f = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("opening {}", path.display()))?;
+        f.write_all(line.as_bytes()).with_context(|| format!("writing {}", path.display()))
+    }
+
+    /// `refname`'s reflog (`HEAD` or `refs/heads/<name>`), oldest first. Lines that
+    /// don't parse are skipped.
+    pub fn reflog(&self, refname: &str) -> Result<Vec<ReflogEntry>> {
+        let path = self.reflog_path(refname);
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e).with_context(|| format!("reading {}", path.display())),
+        };
+        let parse = |line: &str| -> Option<ReflogEntry> {
+            let (head, message) = line.split_once('\t').unwrap_or((line, ""));
+            let (old, rest) = head.split_once(' ')?;
+            let (new, who) = rest.split_once(' ')?;
+            let (who, when) = parse_ident_line(who)?;
+            Some(ReflogEntry {
+                old: Some(old.to_string()).filter(|o| o.bytes().any(|b| b != b'0')),
+                new: new.to_string(),
+                who: who.to_string(),
+                timestamp: when.to_rfc3339(),
+                message: message.to_string(),
+            })
+        };
+        Ok(text.lines().filter_map(parse).collect())
+    }
+
+    /* -------- HEAD -------- */
+
+    /// Where HEAD points. A repository with no HEAD file yet is on an unborn `main`.
+    pub fn head_ref(&self) -> Result<Head> {
+        let path = self.head_path();
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e).with_context(|| format!("reading {}", path.display())),
+        };
+        let text = text.trim();
+        if text.is_empty() {
+            return Ok(Head::Branch(DEFAULT_BRANCH.to_string()));
+        }
+        if let Some(target) = text.strip_prefix("ref:") {
+            let target = target.trim();
+            let name = target.strip_prefix("refs/heads/").ok_or_else(|| Error::Corrupt {
+                what: path.display().to_string(),
+                reason: format!("HEAD points outside refs/heads: {target}"),
+            })?;
+            return Ok(Head::Branch(name.to_string()));
+        }
+        self.objects().check_id(text).map_err(|err| Error::Corrupt { what: path.display().to_string(), reason: err.to_string() })?;
+        Ok(Head::Detached(text.to_string()))
+    }
+
+    pub(crate) fn write_head_ref(&self, head: &Head) -> Result<()> {
+        let text = match head {
+            Head::Branch(name) => format!("ref: refs/heads/{name}\n"),
+            Head::Detached(id) => format!("{id}\n"),
+        };
+        write_atomic(&self.head_path(), text.as_bytes())
+    }
+
+    /// Point HEAD at a branch or commit, logging the move in HEAD's reflog.
+    pub(crate) fn move_head(&self, head: &Head, message: &str) -> Result<()> {
+        let old = self.head()?;
+        self.write_head_ref(head)?;
+        if let Some(new) = self.head()? {
+            self.append_reflog("HEAD", old.as_deref(), &new, message)?;
+        }
+        Ok(())
+    }
+
+    /// HEAD's branch name, or its short commit id when detached (for reflog messages).
+    pub(crate) fn head_description(&self) -> Result<String> {
+        Ok(match self.head_ref()? {
+            Head::Branch(name) => name,
+            Head::Detached(id) => id[..7].to_string(),
+        })
+    }
+
+    /// The commit id HEAD resolves to, or None before the current branch's first commit.
+    pub fn head(&self) -> Result<Option<String>> {
+        match self.head_ref()? {
+            Head::Branch(name) => self.branch_tip(&name),
+            Head::Detached(id) => Ok(Some(id)),
+        }
+    }
+
+    /// Move whatever HEAD names (the current branch, or HEAD itself when detached) to `id`;
+    /// `message` goes in the reflogs.
+    pub fn advance_head(&self, id: &str, message: &str) -> Result<()> {
+        match self.head_ref()? {
+            Head::Branch(name) => self.write_branch(&name, id, message),
+            Head::Detached(_) => self.move_head(&Head::Detached(id.to_string()), message),
+        }
+    }
+
+    /* -------- branches -------- */
+
+    /// The commit a branch points at (None if there's no such branch).
+    pub fn branch_tip(&self, name: &str) -> Result<Option<String>> {
+        if check_ref_name(name, "branch").is_err() {
+            return Ok(None);
+        }
+        self.read_ref(&self.branch_path(name), || format!("branch {name}"))
+    }
+
+    /// Point branch `name` at `id`, logging it in the branch's reflog (and HEAD's, when
+    /// it's the current branch).
+    fn write_branch(&self, name: &str, id: &str, message: &str) -> Result<()> {
+        let old = self.branch_tip(name)?;
+        self.write_ref(&self.branch_path(name), id)?;
+        self.append_reflog(&format!("refs/heads/{name}"), old.as_deref(), id, message)?;
+        if self.head_ref()? == Head::Branch(name.to_string()) {
+            self.append_reflog("HEAD", old.as_deref(), id, message)?;
+        }
+        Ok(())
+    }
+
+    /// Delete branch `name` along with its reflog.
+    fn remove_branch_file(&self, name: &str) -> Result<()> {
+        self.remove_ref(&self.heads_dir(), &self.branch_path(name))?;
+        let log = self.reflog_path(&format!("refs/heads/{name}"));
+        if log.exists() {
+            self.remove_ref(&self.git_dir().join("logs").join("refs").join("heads"), &log)?;
+        }
+        Ok(())
+    }
+
+    /// Every branch as (name, tip), sorted by name.
+    pub fn branches(&self) -> Result<Vec<(String, String)>> {
+        let mut out = Vec::new();
+        for name in self.ref_names(&self.heads_dir())? {
+            if let Some(tip) = self.branch_tip(&name)? {
+                out.push((name, tip));
+            }
+        }
+        Ok(out)
+    }
+
+    /// Is `ancestor` reachable from `descendant` by following parent links?
+    pub fn is_ancestor(&self, ancestor: &str, descendant: &str) -> Result<bool> {
+        Ok(self.commit_graph()?.is_ancestor(ancestor, descendant))
+    }
+
+    /// Create branch `name` at `start` (a revision; HEAD if None) and return its tip.
+    /// With `force`, an existing branch (other than the current one) is reset instead.
+    pub fn create_branch(&self, name: &str, start: Option<&str>, force: bool) -> Result<String> {
+        check_ref_name(name, "branch")?;
+        if self.branch_tip(name)?.is_some() {
+            if !force {
+                return Err(Error::RefExists { kind: "branch", name: name.to_string() });
+            }
+            if self.head_ref()? == Head::Branch(name.to_string()) {
+                return Err(Error::Invalid(format!("cannot force update the current branch '{name}'")));
+            }
+        }
+        let tip = match start {
+            Some(rev) => self.find_commit(rev)?.id,
+            None => self.head()?.ok_or_else(|| Error::UnknownRevision("HEAD".to_string()))?,
+        };
+        self.write_branch(name, &tip, &format!("branch: Created from {}", start.unwrap_or("HEAD")))?;
+        Ok(tip)
+    }
+
+    /// Delete a branch and return the commit it pointed at. Without `force`, only branches
+    /// whose tip HEAD already contains can go (their commits would be lost otherwise).
+    pub fn delete_branch(&self, name: &str, force: bool) -> Result<String> {
+        let tip = self.branch_tip(name)?.ok_or_else(|| Error::RefNotFound { kind: "branch", name: name.to_string() })?;
+        if self.head_ref()? == Head::Branch(name.to_string()) {
+            return Err(Error::Invalid(format!("cannot delete branch '{name}': it is checked out")));
+        }
+        let merged = match self.head()? {
+            Some(head) => self.is_ancestor(&tip, &head)?,
+            None => false,
+        };
+        if !merged && !force {
+            return Err(Error::BranchNotMerged(name.to_string()));
+        }
+        self.remove_branch_file(name)?;
+        Ok(tip)
+    }
+
+    /// Rename branch `old` to `new` (with `force`, replacing an existing `new`). HEAD
+    /// follows if it was on `old`, which may still be unborn.
+    pub fn rename_branch(&self, old: &str, new: &str, force: bool) -> Result<()> {
+        check_ref_name(new, "branch")?;
+        let current = self.head_ref()? == Head::Branch(old.to_string());
+        let tip = self.branch_tip(old)?;
+        if tip.is_none() && !current {
+            return Err(Error::RefNotFound { kind: "branch", name: old.to_string() });
+        }
+        if old == new {
+            return Ok(());
+        }
+        if self.branch_tip(new)?.is_some() && !force {
+            return Err(Error::RefExists { kind: "branch", name: new.to_string() });
+        }
+        if let Some(tip) = tip {
+            // the reflog moves with the branch
+            let old_log = self.reflog_path(&format!("refs/heads/{old}"));
+            let new_log = self.reflog_path(&format!("refs/heads/{new}"));
+            let history = fs::read(&old_log).unwrap_or_default();
+            if let Some(dir) = new_log.parent() {
+                fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+            }
+            fs::write(&new_log, history).with_context(|| format!("writing {}", new_log.display()))?;
+            self.remove_branch_file(old)?;
+            if current {
+                self.write_head_ref(&Head::Branch(new.to_string()))?;
+            }
+            self.write_branch(new, &tip, &format!("Branch: renamed refs/heads/{old} to refs/heads/{new}"))?;
+        } else {
+            self.write_head_ref(&Head::Branch(new.to_string()))?;
+        }
+        Ok(())
+    }
+
+    /// Check out branch `name` and put HEAD on it. Returns None when there was nothing to
+    /// check out (already on it). Without `force`, refuses to overwrite local changes.
+    pub fn switch(&self, name: &str, force: bool) -> Result<Option<Checkout>> {
+        if self.head_ref()? == Head::Branch(name.to_string()) {
+            return Ok(None);
+        }
+        let tip = self.branch_tip(name)?.ok_or_else(|| Error::RefNotFound { kind: "branch", name: name.to_string() })?;
+        let target = self.find_commit(&tip)?;
+        let done = self.checkout_tree(target, force)?;
+        let message = format!("checkout: moving from {} to {name}", self.head_description()?);
+        self.move_head(&Head::Branch(name.to_string()), &message)?;
+        Ok(Some(done))
+    }
+
+    /// Create branch `name` at `start` (HEAD if None) and switch to it. On an unborn HEAD
+    /// with no start point, just starts the new branch there (None: nothing checked out).
+    pub fn switch_new(&self, name: &str, start: Option<&str>, force: bool) -> Result<Option<Checkout>> {
+        check_ref_name(name, "branch")?;
+        if self.branch_tip(name)?.is_some() {
+            return Err(Error::RefExists { kind: "branch", name: name.to_string() });
+        }
+        let message = format!("checkout: moving from {} to {name}", self.head_description()?);
+        let created = format!("branch: Created from {}", start.unwrap_or("HEAD"));
+        let done = match (start, self.head()?) {
+            (None, None) => None,
+            (None, Some(head)) => {
+                self.write_branch(name, &head, &created)?;
+                None
+            }
+            (Some(rev), _) => {
+                // check out first, so a refused checkout leaves no branch behind
+                let target = self.find_commit(rev)?;
+                let id = target.id.clone();
+                let done = self.checkout_tree(target, force)?;
+                self.write_branch(name, &id, &created)?;
+                Some(done)
+            }
+        };
+        self.move_head(&Head::Branch(name.to_string()), &message)?;
+        Ok(done)
+    }
+
+    /* -------- tags -------- */
+
+    /// What tag `name` stores: a commit id, or an annotated tag object's id (None if
+    /// there's no such tag).
+    pub fn tag_ref(&self, name: &str) -> Result<Option<String>> {
+        if check_ref_name(name, "tag").is_err() {
+            return Ok(None);
+        }
+        self.read_ref(&self.tags_dir().join(name), || format!("tag {name}"))
+    }
+
+    /// The annotated tag object `id` names, or None if it's a plain commit id.
+    pub fn read_tag(&self, id: &str) -> Result<Option<Tag>> {
+        if !self.objects().contains(id) || self.objects().read(id)?.0 != "tag" {
+            return Ok(None);
+        }
+        Tag::read(self.objects(), id).map(Some)
+    }
+
+    /// The commit tag `name` points at, looking through annotated tag objects.
+    pub fn tag_target(&self, name: &str) -> Result<Option<String>> {
+        let Some(mut id) = self.tag_ref(name)? else { return Ok(None) };
+        while let Some(tag) = self.read_tag(&id)? {
+            id = tag.object;
+        }
+        Ok(Some(id))
+    }
+
+    /// Every tag name, sorted.
+    pub fn tags(&self) -> Result<Vec<String>> {
+        self.ref_names(&self.tags_dir())
+    }
+
+    /// Tag the commit `rev` as `name`: lightweight (the ref holds the commit id), or with
+    /// a `message`, annotated (the ref holds a new tag object's id). With `force`, an
+    /// existing tag is replaced; returns what it pointed at before.
+    pub fn create_tag(&self, name: &str, rev: &str, message: Option<&str>, force: bool) -> Result<Option<String>> {
+        check_ref_name(name, "tag")?;
+        let old = self.tag_ref(name)?;
+        if old.is_some() && !force {
+            return Err(Error::RefExists { kind: "tag", name: name.to_string() });
+        }
+        let commit = self.find_commit(rev)?;
+        let id = match message {
+            Some(message) => Tag::new(self.objects(), name, &commit.id, author_ident(), message)?.id,
+            None => commit.id,
+        };
+        self.write_ref(&self.tags_dir().join(name), &id)?;
+        Ok(old)
+    }
+
+    /// Delete tag `name` and return what it pointed at (a tag object stays in the store).
+    pub fn delete_tag(&self, name: &str) -> Result<String> {
+        let id = self.tag_ref(name)?.ok_or_else(|| Error::RefNotFound { kind: "tag", name: name.to_string() })?;
+        self.remove_ref(&self.tags_dir(), &self.tags_dir().join(name))?;
+        Ok(id)
+    }
+}
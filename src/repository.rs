@@ -0,0 +1,453 @@
+//! A repository on disk: where `.minigit/` and its worktree are, the config chosen at
+//! `init`, and access to the index and the commit log (HEAD and branches are in `refs`).
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+
+use serde::{Deserialize, Serialize};
+
+use crate::commit::{author_ident, Commit};
+use crate::error::{Context, Error, Result};
+use crate::graph::CommitGraph;
+use crate::index::{Index, IndexLock, Snapshot};
+use crate::objects::{decode_object, ObjectFormat, ObjectStore};
+use crate::refs::DEFAULT_BRANCH;
+use crate::util::{normalize, sync_dir, write_atomic};
+
+/* -------- config -------- */
+
+/// Per-repository settings chosen at `init`, stored in `.minigit/config.json`.
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct Config {
+    /// Hash used for every object id (repos from before this setting are SHA-1).
+    #[serde(default)]
+    pub object_format: ObjectFormat,
+    /// Hash and serialize blobs, trees and commits exactly like git loose objects,
+    /// so ids match `git hash-object` and real git can read `.minigit/`.
+    #[serde(default)]
+    pub git_compatible: bool,
+}
+
+/* -------- repository -------- */
+
+/// An open repository. Relative paths handed to its methods are resolved against its
+/// current directory (see [`Repository::with_cwd`]), never the process's.
+#[derive(Debug)]
+pub struct Repository {
+    git_dir: PathBuf,
+    worktree: PathBuf,
+    cwd: PathBuf,
+    config: Config,
+    objects: ObjectStore,
+    /// The commit log, parsed on first use; `append_commit` drops it.
+    history: Mutex<Option<Arc<History>>>,
+}
+
+/// The commit log in memory: every commit in log order, and the graph over them (which
+/// numbers commits the same way).
+#[derive(Debug)]
+pub(crate) struct History {
+    pub(crate) commits: Vec<Commit>,
+    pub(crate) graph: Arc<CommitGraph>,
+}
+
+impl History {
+    /// The commit with id `id` (a full id).
+    pub(crate) fn get(&self, id: &str) -> Option<&Commit> {
+        self.graph.position(id).map(|i| &self.commits[i])
+    }
+}
+
+impl Repository {
+    /// Find the nearest `.minigit/` in `start` or any parent; its parent is the worktree.
+    pub fn discover(start: &Path) -> Result<Self> {
+        let mut dir = Some(start);
+        while let Some(d) = dir {
+            let candidate = d.join(".minigit");
+            if candidate.is_dir() {
+                return Ok(Repository::load(candidate, d.to_path_buf())?.with_cwd(start));
+            }
+            dir = d.parent();
+        }
+        Err(Error::NoRepository { start: start.to_path_buf() })
+    }
+
+    /// Open the repository directory `git_dir` (e.g., from `--git-dir`) tracking `worktree`.
+    pub fn open(git_dir: &Path, worktree: &Path) -> Result<Self> {
+        if !git_dir.is_dir() {
+            return Err(Error::MissingGitDir(git_dir.to_path_buf()));
+        }
+        let git_dir = git_dir.canonicalize().with_context(|| format!("resolving {}", git_dir.display()))?;
+        Repository::load(git_dir, worktree.to_path_buf())
+    }
+
+    /// Create an empty repository in `git_dir` for `worktree`.
+    pub fn init(git_dir: &Path, worktree: &Path, config: Config) -> Result<Self> {
+        if git_dir.exists() {
+            return Err(Error::AlreadyInitialized(git_dir.to_path_buf()));
+        }
+        if config.git_compatible && config.object_format == ObjectFormat::Blake3 {
+            return Err(Error::Invalid(
+                "git has no blake3 object format; use --object-format=sha1 or sha256 with --git-compat".to_string(),
+            ));
+        }
+        let create = |dir: &Path| fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()));
+        create(&git_dir.join("objects"))?;
+        // refs/ is what real git looks for (with HEAD and objects/) to accept a repository directory
+        create(&git_dir.join("refs").join("heads"))?;
+        write_atomic(&git_dir.join("HEAD"), format!("ref: refs/heads/{DEFAULT_BRANCH}\n").as_bytes())?;
+        let json = serde_json::to_vec_pretty(&config).with_context(|| "serializing config")?;
+        write_atomic(&git_dir.join("config.json"), &json)?;
+        if config.git_compatible && config.object_format == ObjectFormat::Sha256 {
+            // real git only reads SHA-256 objects when its own config says so
+            let path = git_dir.join("config");
+            fs::write(&path, "[core]\n\trepositoryformatversion = 1\n[extensions]\n\tobjectformat = sha256\n")
+                .with_context(|| format!("writing {}", path.display()))?;
+        }
+        let repo = Repository::load(git_dir.to_path_buf(), worktree.to_path_buf())?;
+        repo.save_index(&Index::new(), repo.lock_index()?)?;
+        Ok(repo)
+    }
+
+    /// Read the config (repos without a config file get the defaults).
+    fn load(git_dir: PathBuf, worktree: PathBuf) -> Result<Self> {
+        let config_path = git_dir.join("config.json");
+        let config: Config = if config_path.exists() {
+            let bytes = fs::read(&config_path).with_context(|| format!("reading {}", config_path.display()))?;
+            serde_json::from_slice(&bytes).with_context(|| "parsing config.json as JSON")?
+        } else {
+            Config::default()
+        };
+        let objects = ObjectStore::new(git_dir.join("objects"), config.object_format, config.git_compatible);
+        Ok(Repository { cwd: worktree.clone(), git_dir, worktree, config, objects, history: Mutex::default() })
+    }
+
+    /// Resolve relative paths against `dir` (the worktree root by default).
+    pub fn with_cwd(mut self, dir: &Path) -> Self {
+        self.cwd = normalize(&self.worktree.join(dir));
+        self
+    }
+
+    /// The `.minigit` directory.
+    pub fn git_dir(&self) -> &Path {
+        &self.git_dir
+    }
+
+    /// Top of the working tree; every stored path is relative to this.
+    pub fn worktree(&self) -> &Path {
+        &self.worktree
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn objects(&self) -> &ObjectStore {
+        &self.objects
+    }
+
+    fn index_path(&self) -> PathBuf {
+        self.git_dir.join("index.bin")
+    }
+
+    /// Pre-binary index: a JSON map of path -> blob id. Still read, replaced on the next save.
+    fn legacy_index_path(&self) -> PathBuf {
+        self.git_dir.join("index.json")
+    }
+
+    fn index_lock_path(&self) -> PathBuf {
+        self.git_dir.join("index.lock")
+    }
+
+    fn commits_path(&self) -> PathBuf {
+        self.git_dir.join("commits.jsonl")
+    }
+
+    /* -------- paths -------- */
+
+    /// `path` made absolute against the repository's current directory.
+    pub fn resolve(&self, path: &Path) -> PathBuf {
+        normalize(&self.cwd.join(path))
+    }
+
+    /// Turn a path (relative to the current directory) into the worktree-relative,
+    /// `/`-separated form stored in the index and commits.
+    pub fn to_repo_relative(&self, path: &Path) -> Result<String> {
+        let abs = self.resolve(path);
+        let rel = abs.strip_prefix(&self.worktree).map_err(|_| Error::OutsideRepository {
+            path: path.to_path_buf(),
+            worktree: self.worktree.clone(),
+        })?;
+        let parts: Vec<String> = rel.components().map(|c| c.as_os_str().to_string_lossy().into_owned()).collect();
+        Ok(parts.join("/"))
+    }
+
+    /* -------- index -------- */
+
+    /// Take the index lock, waiting up to 10 s for another process to release it.
+    pub fn lock_index(&self) -> Result<IndexLock> {
+        IndexLock::acquire(&self.index_lock_path())
+    }
+
+    /// Take the index lock only if it's free right now.
+    pub fn try_lock_index(&self) -> Result<Option<IndexLock>> {
+        IndexLock::try_acquire(&self.index_lock_path())
+    }
+
+    /// Load the index: the binary `.minigit/index.bin`, or a legacy `index.json`
+    /// (path -> blob id, no stat data). If neither exists, return an empty index.
+    pub fn load_index(&self) -> Result<Index> {
+        let (path, legacy) = (self.index_path(), self.legacy_index_path());
+        let idx = if path.exists() {
+            // Read the whole file into bytes
+            let bytes = fs::read(&path).with_context(|| format!("reading {}", path.display()))?;
+            Index::decode(&bytes).map_err(|reason| Error::Corrupt { what: path.display().to_string(), reason })?
+        } else if legacy.exists() {
+            let bytes = fs::read(&legacy).with_context(|| format!("reading {}", legacy.display()))?;
+            Index::from_legacy_json(&bytes)?
+        } else {
+            return Ok(Index::new());
+        };
+
+        // Every blob id must match this repo's object format
+        for (path, e) in &idx {
+            self.objects.check_id(&e.blob).map_err(|err| Error::Corrupt {
+                what: format!("index entry {path}"),
+                reason: err.to_string(),
+            })?;
+        }
+
+        Ok(idx)
+    }
+
+    /// Save the index in the binary format to `.minigit/index.bin`.
+    /// The data goes into the lock file, is fsynced, and is renamed over the index,
+    /// so readers see either the old index or the new one, never a truncated file.
+    pub fn save_index(&self, index: &Index, mut lock: IndexLock) -> Result<()> {
+        use std::io::Write;
+
+        // Serialize entries and stat data
+        let data = index.encode()?;
+
+        // Write to disk
+        lock.file
+            .write_all(&data)
+            .and_then(|()| lock.file.sync_all())
+            .with_context(|| format!("writing {}", lock.path.display()))?;
+        fs::rename(&lock.path, self.index_path())
+            .with_context(|| format!("replacing {}", self.index_path().display()))?;
+        lock.saved = true;
+
+        // A repo upgraded from index.json no longer needs it
+        let legacy = self.legacy_index_path();
+        if legacy.exists() {
+            fs::remove_file(&legacy).with_context(|| format!("removing {}", legacy.display()))?;
+        }
+        sync_dir(&self.git_dir)
+    }
+
+    /* -------- commits -------- */
+
+    /// Snapshot of the HEAD commit (empty before the first commit).
+    pub fn head_tree(&self) -> Result<Snapshot> {
+        match self.head()? {
+            Some(id) => match self.history()?.get(&id) {
+                Some(commit) => Ok(commit.snapshot()),
+                None => Err(Error::UnknownRevision(id)),
+            },
+            None => Ok(Snapshot::new()),
+        }
+    }
+
+    /// Every commit from `.minigit/commits.jsonl` (one JSON object per line).
+    pub fn commits(&self) -> Result<Vec<Commit>> {
+        Ok(self.history()?.commits.clone())
+    }
+
+    /// The commit log and its graph, read once per `Repository` (and again after a commit).
+    pub(crate) fn history(&self) -> Result<Arc<History>> {
+        let mut cached = self.history.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(history) = &*cached {
+            return Ok(history.clone());
+        }
+        let commits = self.read_commit_log()?;
+        let graph = Arc::new(CommitGraph::build(&commits)?);
+        let history = Arc::new(History { commits, graph });
+        *cached = Some(history.clone());
+        Ok(history)
+    }
+
+    fn read_commit_log(&self) -> Result<Vec<Commit>> {
+        let path = self.commits_path();
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+        let text = fs::read_to_string(&path).with_context(|| format!("reading {}", path.display()))?;
+        // A final line without '\n' is a write cut short by a crash; HEAD never pointed at it.
+        let complete = &text[..text.rfind('\n').map_or(0, |i| i + 1)];
+        complete
+            .lines()
+            .filter(|l| !l.trim().is_empty())
+            .enumerate()
+            .map(|(i, l)| {
+                let c: Commit = serde_json::from_str(l).with_context(|| format!("parsing commits.jsonl line {}", i + 1))?;
+                self.objects.check_id(&c.id).map_err(|err| Error::Corrupt {
+                    what: format!("commits.jsonl line {}", i + 1),
+                    reason: err.to_string(),
+                })?;
+                Ok(c)
+            })
+            .collect()
+    }
+
+    /// Look up the commit a revision names (`HEAD~2`, a branch or tag, an id prefix, ...;
+    /// see [`Repository::rev_parse`]). Annotated tags resolve to the commit they tag.
+    pub fn find_commit(&self, rev: &str) -> Result<Commit> {
+        let id = self.resolve_object(rev)?;
+        self.peel_to_commit(&id).map_err(|e| match e {
+            Error::UnknownRevision(_) => Error::UnknownRevision(rev.to_string()),
+            e => e,
+        })
+    }
+
+    /// The commit object `id` (e.g., from [`Repository::rev_parse`]) is or, for an
+    /// annotated tag, points at.
+    pub fn peel_to_commit(&self, id: &str) -> Result<Commit> {
+        let mut id = id.to_string();
+        while let Some(tag) = self.read_tag(&id)? {
+            id = tag.object;
+        }
+        match self.history()?.get(&id) {
+            Some(commit) => Ok(commit.clone()),
+            None if self.objects().contains(&id) => {
+                let kind = self.objects().read(&id)?.0;
+                Err(Error::WrongObjectKind { id, kind, expected: "commit" })
+            }
+            None => Err(Error::UnknownRevision(id)),
+        }
+    }
+
+    /// Append one commit as a single JSON line and fsync it (callers hold the index lock,
+    /// which serializes commits). A torn line left by an earlier crash is trimmed first.
+    pub fn append_commit(&self, commit: &Commit) -> Result<()> {
+        use std::io::{Seek, SeekFrom, Write};
+        let path = self.commits_path();
+        let mut line = serde_json::to_vec(commit).with_context(|| "serializing commit")?;
+        line.push(b'\n');
+        let mut f = fs::OpenOptions::new()
+            .create(true)
+            .read(true)
+            .write(true)
+            .truncate(false)
+            .open(&path)
+            .with_context(|| format!("opening {}", path.display()))?;
+        let existing = fs::read(&path).with_context(|| format!("reading {}", path.display()))?;
+        let keep = existing.iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+        let result = (|| {
+            f.set_len(keep as u64)?;
+            f.seek(SeekFrom::End(0))?;
+            f.write_all(&line)?;
+            f.sync_all()
+        })();
+        // the log changed under the cached history
+        *self.history.lock().unwrap_or_else(PoisonError::into_inner) = None;
+        result.with_context(|| format!("writing {}", path.display()))
+    }
+
+    /// Record the index as a new commit on top of HEAD and move the current branch
+    /// (or a detached HEAD) to it. During a merge, the commit being merged in becomes
+    /// the second parent (and the index must have no conflicts left).
+    pub fn commit(&self, message: String) -> Result<Commit> {
+        // 1) snapshot the index (sorted, so ids are reproducible); holding the
+        //    lock keeps a concurrent add or commit from racing HEAD
+        let _lock = self.lock_index()?;
+        let index = self.load_index()?;
+        if !index.conflicts.is_empty() {
+            return Err(Error::Unmerged(index.conflicts.keys().cloned().collect()));
+        }
+        let tree = index.snapshot();
+
+        // 2) refuse empty commits: nothing changed since the parent snapshot (a merge
+        //    commit records the join even when the tree didn't change)
+        let parent = self.head()?;
+        let merging = self.merge_head()?;
+        if parent.is_some() {
+            if merging.is_none() && self.head_tree()? == tree {
+                return Err(Error::NothingToCommit("index matches HEAD"));
+            }
+        } else if tree.is_empty() {
+            return Err(Error::NothingToCommit("index is empty; use `mini-git add` first"));
+        }
+
+        // 3) record it, then move the branch forward
+        let parents = parent.into_iter().chain(merging).collect();
+        let commit = Commit::new(&self.objects, parents, author_ident(), message, &tree)?;
+        self.append_commit(&commit)?;
+        let kind = match commit.parents().len() {
+            0 => " (initial)",
+            1 => "",
+            _ => " (merge)",
+        };
+        self.advance_head(&commit.id, &format!("commit{kind}: {}", commit.subject()))?;
+        self.clear_merge_state()?;
+        Ok(commit)
+    }
+
+    /* -------- fsck -------- */
+
+    /// Verify every object's content matches its id and that everything the index and
+    /// commits reference exists, and find objects still in the old flat layout.
+    /// With `migrate`, flat objects are moved into their `objects/ab/` shard.
+    pub fn fsck(&self, migrate: bool) -> Result<FsckReport> {
+        let mut report = FsckReport::default();
+
+        // 1) every stored object: readable, and named after its content
+        for (id, path) in self.objects.list()? {
+            let raw = fs::read(&path).with_context(|| format!("reading {}", path.display()))?;
+            let (kind, data) = decode_object(&raw).unwrap_or_else(|| ("blob".to_string(), raw));
+            let actual = self.objects.hash(&kind, &data);
+            if actual != id {
+                report.corrupt.push((id, actual));
+                continue;
+            }
+            if path == self.objects.flat_path(&id) {
+                if migrate {
+                    self.objects.move_to_fanout(&id)?;
+                }
+                report.flat.push(id);
+            }
+        }
+
+        // 2) every referenced blob exists
+        let mut referenced: BTreeMap<String, String> = BTreeMap::new();
+        for (path, e) in &self.load_index()? {
+            referenced.entry(e.blob.clone()).or_insert(format!("index entry {path}"));
+        }
+        for c in self.commits()? {
+            for (path, blob) in &c.tree {
+                referenced.entry(blob.clone()).or_insert(format!("{path} in commit {}", &c.id[..7]));
+            }
+        }
+        report.missing = referenced.into_iter().filter(|(blob, _)| !self.objects.contains(blob)).collect();
+        Ok(report)
+    }
+}
+
+/// What `fsck` found.
+#[derive(Default, Debug)]
+pub struct FsckReport {
+    /// (id, what the content actually hashes to)
+    pub corrupt: Vec<(String, String)>,
+    /// (blob id, what references it)
+    pub missing: Vec<(String, String)>,
+    /// Objects in the old flat layout (already moved if `migrate` was asked for).
+    pub flat: Vec<String>,
+}
+
+impl FsckReport {
+    pub fn problems(&self) -> usize {
+        self.corrupt.len() + self.missing.len()
+    }
+}
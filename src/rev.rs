@@ -0,0 +1,223 @@
+//! Revision expressions, shared by every command that takes a commit: full and
+//! abbreviated ids, `HEAD`, branch and tag names, `name@{n}` reflog entries, `~n` / `^n`
+//! ancestry suffixes, `a..b` / `a...b` ranges, and `rev:path` / `:path` for blobs.
+
+use crate::error::{Error, Result};
+use crate::objects::ObjectFormat;
+use crate::refs::Head;
+use crate::repository::Repository;
+
+/// Shortest id prefix accepted as an abbreviation (like git).
+pub const MIN_ABBREV: usize = 4;
+
+const ALL_FORMATS: [ObjectFormat; 3] = [ObjectFormat::Sha1, ObjectFormat::Sha256, ObjectFormat::Blake3];
+
+/// What a revision expression names.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Revision {
+    /// One object: usually a commit, or a blob from `rev:path` / `:path`.
+    Single(String),
+    /// `from..to`: commits reachable from `to` but not from `from`.
+    Range { from: String, to: String },
+    /// `left...right`: commits reachable from one side but not both; `bases` are the
+    /// sides' merge bases.
+    Symmetric { left: String, right: String, bases: Vec<String> },
+}
+
+/// Split `HEAD~2^2` into the base (`HEAD`) and its suffixes (`~2`, `^2`) as (op, count).
+fn split_suffixes(expr: &str) -> Result<(&str, Vec<(char, usize)>)> {
+    let Some(start) = expr.find(['~', '^']) else { return Ok((expr, Vec::new())) };
+    let bad = || Error::UnknownRevision(expr.to_string());
+    let mut suffixes = Vec::new();
+    let mut rest = &expr[start..];
+    while let Some(op) = rest.chars().next() {
+        if op != '~' && op != '^' {
+            return Err(bad());
+        }
+        let digits = rest[1..].find(|c: char| !c.is_ascii_digit()).map_or(rest.len() - 1, |i| i);
+        let count = if digits == 0 { 1 } else { rest[1..=digits].parse().map_err(|_| bad())? };
+        suffixes.push((op, count));
+        rest = &rest[1 + digits..];
+    }
+    Ok((&expr[..start], suffixes))
+}
+
+impl Repository {
+    /// Parse a revision expression: a single revision, `a..b` or `a...b` (a missing side
+    /// means HEAD).
+    pub fn rev_parse(&self, expr: &str) -> Result<Revision> {
+        // `rev:path` may have ".." in its path, so it's never a range
+        if !expr.contains(':') {
+            let side = |s: &str| self.find_commit(if s.is_empty() { "HEAD" } else { s }).map(|c| c.id);
+            if let Some((left, right)) = expr.split_once("...") {
+                let (left, right) = (side(left)?, side(right)?);
+                let bases = self.merge_bases(&left, &right)?;
+                return Ok(Revision::Symmetric { left, right, bases });
+            }
+            if let Some((from, to)) = expr.split_once("..") {
+                return Ok(Revision::Range { from: side(from)?, to: side(to)? });
+            }
+        }
+        self.resolve_object(expr).map(Revision::Single)
+    }
+
+    /// The object id a single revision names (a commit, or a blob for `rev:path`).
+    pub fn resolve_object(&self, expr: &str) -> Result<String> {
+        if let Some((rev, path)) = expr.split_once(':') {
+            return self.resolve_path(rev, path);
+        }
+        let (base, suffixes) = split_suffixes(expr)?;
+        let mut id = self.resolve_base(if base.is_empty() { "HEAD" } else { base })?;
+        if suffixes.is_empty() {
+            return Ok(id);
+        }
+
+        // walk the ancestry: `~n` follows first parents n times, `^n` takes the n-th parent
+        let graph = self.commit_graph()?;
+        while let Some(tag) = self.read_tag(&id)? {
+            id = tag.object;
+        }
+        let missing = || Error::UnknownRevision(expr.to_string());
+        if !graph.contains(&id) {
+            return Err(missing());
+        }
+        for (op, count) in suffixes {
+            let steps = match op {
+                '~' => vec![0; count],
+                _ if count == 0 => Vec::new(),
+                _ => vec![count - 1],
+            };
+            for nth in steps {
+                id = graph.parents(&id).get(nth).ok_or_else(missing)?.to_string();
+            }
+        }
+        Ok(id)
+    }
+
+    /// `rev:path` (a blob in a commit) or `:path` (a blob in the index). Paths are from the
+    /// top of the worktree unless they start with `./` or `../`.
+    fn resolve_path(&self, rev: &str, path: &str) -> Result<String> {
+        let rel = if path.starts_with("./") || path.starts_with("../") {
+            self.to_repo_relative(path.as_ref())?
+        } else {
+            path.trim_end_matches('/').to_string()
+        };
+        if rev.is_empty() {
+            let index = self.load_index()?;
+            let entry = index.get(&rel).ok_or_else(|| Error::Invalid(format!("path '{rel}' is not in the index")))?;
+            return Ok(entry.blob.clone());
+        }
+        let tree = self.find_commit(rev)?.snapshot();
+        if let Some(entry) = tree.get(&rel) {
+            return Ok(entry.blob.clone());
+        }
+        let prefix = format!("{rel}/");
+        if rel.is_empty() || tree.keys().any(|p| p.starts_with(&prefix)) {
+            return Err(Error::Invalid(format!("'{path}' is a directory in '{rev}', not a file")));
+        }
+        Err(Error::Invalid(format!("path '{rel}' does not exist in '{rev}'")))
+    }
+
+    /// A revision without suffixes: `HEAD`, a reflog entry, a tag, a branch, or an id.
+    fn resolve_base(&self, name: &str) -> Result<String> {
+        let unknown = || Error::UnknownRevision(name.to_string());
+
+        // 1) `name@{n}`: the n-th previous value in a reflog (`@{n}`: the current branch's)
+        if let Some((refname, n)) = name.strip_suffix('}').and_then(|s| s.split_once("@{")) {
+            let n: usize = n.parse().map_err(|_| unknown())?;
+            let (label, log) = match refname {
+                "HEAD" => ("HEAD".to_string(), "HEAD".to_string()),
+                "" | "@" => match self.head_ref()? {
+                    Head::Branch(branch) => (branch.clone(), format!("refs/heads/{branch}")),
+                    Head::Detached(_) => ("HEAD".to_string(), "HEAD".to_string()),
+                },
+                _ => {
+                    let branch = refname.strip_prefix("refs/heads/").or(refname.strip_prefix("heads/")).unwrap_or(refname);
+                    self.branch_tip(branch)?.ok_or_else(unknown)?;
+                    (branch.to_string(), format!("refs/heads/{branch}"))
+                }
+            };
+            let entries = self.reflog(&log)?;
+            let entry = entries.len().checked_sub(n + 1).map(|i| &entries[i]);
+            let entry = entry.ok_or_else(|| Error::Invalid(format!("log for '{label}' only has {} entries", entries.len())))?;
+            return Ok(entry.new.clone());
+        }
+
+        // 2) refs: HEAD, then tags, then branches (`tags/` and `heads/` pick one explicitly)
+        if name == "HEAD" || name == "@" {
+            return self.head()?.ok_or_else(unknown);
+        }
+        let short = name.strip_prefix("refs/").unwrap_or(name);
+        if let Some(tag) = short.strip_prefix("tags/") {
+            return self.tag_target(tag)?.ok_or_else(unknown);
+        }
+        if let Some(branch) = short.strip_prefix("heads/") {
+            return self.branch_tip(branch)?.ok_or_else(unknown);
+        }
+        if let Some(id) = self.tag_target(name)? {
+            return Ok(id);
+        }
+        if let Some(id) = self.branch_tip(name)? {
+            return Ok(id);
+        }
+
+        // 3) a full id, or a unique prefix of a commit or stored object
+        // (a full id in another object format is a mixup worth naming, not an unknown name)
+        let hex = name.bytes().all(|b| b.is_ascii_hexdigit());
+        let full_length = hex && ALL_FORMATS.iter().any(|f| f.hex_len() == name.len());
+        let not_found = || if full_length { self.objects().check_id(name).and(Err(unknown())) } else { Err(unknown()) };
+        let hex_len = self.objects().format().hex_len();
+        if name.len() < MIN_ABBREV || name.len() > hex_len || !hex {
+            return not_found();
+        }
+        let prefix = name.to_ascii_lowercase();
+        let graph = self.commit_graph()?;
+        let mut candidates: Vec<String> = graph.ids().filter(|id| id.starts_with(&prefix)).map(String::from).collect();
+        for (id, _) in self.objects().list()? {
+            if id.starts_with(&prefix) && !candidates.contains(&id) {
+                candidates.push(id);
+            }
+        }
+        match candidates.len() {
+            0 => not_found(),
+            1 => Ok(candidates.remove(0)),
+            _ => {
+                candidates.sort();
+                Err(Error::AmbiguousRevision { prefix: name.to_string(), candidates })
+            }
+        }
+    }
+
+    /// The best common ancestors of two commits: those shared by both histories that no
+    /// other shared commit descends from, newest first. Usually one; none for unrelated
+    /// histories.
+    pub fn merge_bases(&self, a: &str, b: &str) -> Result<Vec<String>> {
+        Ok(self.commit_graph()?.merge_bases(a, &[b]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::Config;
+    use crate::testing::{commit, scratch_with};
+
+    #[test]
+    fn full_ids_from_another_format_are_named_as_such() {
+        for (format, other) in [(ObjectFormat::Sha1, 64), (ObjectFormat::Sha256, 40)] {
+            let repo = scratch_with(&format!("rev-format-{}", format.name()), Config { object_format: format, ..Config::default() });
+            commit(&repo, &[("f", "x\n")], &[], "one");
+            let id = "ab".repeat(other / 2);
+            let err = repo.rev_parse(&id).unwrap_err();
+            assert!(matches!(&err, Error::ObjectFormatMismatch { format: f, .. } if *f == format), "{err}");
+            let err = repo.find_commit(&format!("{id}~1")).unwrap_err();
+            assert!(matches!(err, Error::ObjectFormatMismatch { .. }), "{err}");
+
+            // ids of the repository's own format, and short prefixes, are just unknown
+            let err = repo.rev_parse(&"ab".repeat(format.hex_len() / 2)).unwrap_err();
+            assert!(matches!(err, Error::UnknownRevision(_)), "{err}");
+            assert!(matches!(repo.rev_parse("abab").unwrap_err(), Error::UnknownRevision(_)));
+            std::fs::remove_dir_all(repo.worktree()).unwrap();
+        }
+    }
+}
@@ -0,0 +1,275 @@
+//! Changing what's staged: `add` (with pathspecs), `rm` and `mv`.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::error::{Context, Error, Result};
+use crate::ignore::{glob_match, Ignore};
+use crate::index::{mode_of, FileEntry, FileStat, Index, IndexEntry};
+use crate::repository::Repository;
+use crate::worktree::read_worktree_file;
+
+/// Does a command-line path use glob syntax (and so get matched rather than looked up)?
+pub fn is_pathspec_glob(path: &Path) -> bool {
+    path.to_string_lossy().contains(['*', '?', '['])
+}
+
+/// Does repo-relative `rel` fall under a glob pathspec? Matching a leading directory
+/// counts, so `src/*` covers everything below each of src's subdirectories too.
+pub fn pathspec_matches(pattern: &str, rel: &str) -> bool {
+    glob_match(pattern, rel) || rel.match_indices('/').any(|(i, _)| glob_match(pattern, &rel[..i]))
+}
+
+/// What `add` did to one path.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Staged {
+    Added,
+    Modified,
+    Removed,
+}
+
+/// How `add` treats its pathspecs.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AddOptions {
+    /// Also add paths excluded by .minigitignore when named explicitly.
+    pub force: bool,
+    /// Only stage modifications and deletions of already-tracked files.
+    pub update: bool,
+    /// Work out the changes without touching the index or object store.
+    pub dry_run: bool,
+}
+
+/// What `add` staged, in path order.
+#[derive(Debug, Default)]
+pub struct AddReport {
+    pub changes: Vec<(String, Staged)>,
+    /// Ignored paths named without `force`, as given.
+    pub skipped: Vec<PathBuf>,
+}
+
+/// What `mv` did: the repo-relative source and destination, and each index entry re-keyed.
+#[derive(Debug)]
+pub struct Move {
+    pub from: String,
+    pub to: String,
+    pub renamed: Vec<(String, String)>,
+}
+
+impl Repository {
+    /// Stage one worktree file; returns what changed in the index (`None` if its content and
+    /// mode were already staged). With `dry_run` nothing is written to the object store.
+    /// Staging a path with merge conflicts marks them resolved.
+    pub fn stage_file(&self, path: &Path, index: &mut Index, dry_run: bool) -> Result<Option<Staged>> {
+        // 1) stat first (without following symlinks): unchanged since we last hashed it ⇒ nothing to do
+        let abs = self.resolve(path);
+        let meta = fs::symlink_metadata(&abs).with_context(|| format!("reading {}", path.display()))?;
+        let rel = self.to_repo_relative(path)?;
+        let resolved = index.conflicts.remove(&rel).is_some();
+        if !resolved && index.get(&rel).is_some_and(|e| e.matches_stat(&meta)) {
+            return Ok(None);
+        }
+
+        // 2) read bytes of the file (for a symlink: its target)
+        let data = read_worktree_file(&abs, &meta)?;
+
+        // 3) hash bytes → blob id, and write the compressed blob once under .minigit/objects/<hash>
+        let blob_id = if dry_run { self.objects().hash("blob", &data) } else { self.objects().write("blob", &data)? };
+
+        // 4) record staging: repo-relative path → mode + blob id (+ the stat data it was hashed at)
+        let entry = IndexEntry { blob: blob_id, mode: mode_of(&meta), stat: FileStat::from_metadata(&meta) };
+        let change = match index.get(&rel) {
+            None => Some(Staged::Added),
+            Some(old) if resolved || old.blob != entry.blob || old.mode != entry.mode => Some(Staged::Modified),
+            Some(_) => None, // same content, just fresher stat data
+        };
+        index.insert(rel, entry);
+
+        Ok(change)
+    }
+
+    /// Stage additions, modifications and deletions under `pathspecs` (files, directories or
+    /// globs; empty means the whole worktree).
+    pub fn add(&self, pathspecs: &[PathBuf], opts: AddOptions) -> Result<AddReport> {
+        let lock = self.lock_index()?;
+        let mut index = self.load_index()?;
+        let mut ignore = Ignore::new(self.worktree());
+        let root = self.worktree().to_path_buf();
+        let mut report = AddReport::default();
+
+        // 1) expand pathspecs into repo-relative paths: files on disk, plus tracked paths that may be gone
+        let specs = if pathspecs.is_empty() { vec![root.clone()] } else { pathspecs.to_vec() };
+        let mut worktree_files: Option<Vec<String>> = None;
+        let mut candidates = BTreeSet::new();
+        for p in &specs {
+            let rel = self.to_repo_relative(p)?;
+            let mut matched: Vec<String> = Vec::new();
+            if is_pathspec_glob(p) {
+                if worktree_files.is_none() {
+                    let files = self.walk(&root, &mut ignore)?;
+                    worktree_files = Some(files.iter().map(|f| self.to_repo_relative(f)).collect::<Result<_>>()?);
+                }
+                let on_disk = worktree_files.iter().flatten();
+                matched.extend(on_disk.chain(index.keys()).chain(index.conflicts.keys()).filter(|f| pathspec_matches(&rel, f)).cloned());
+            } else {
+                // naming an ignored path explicitly still needs --force (tracked files are always fine)
+                let abs = self.resolve(p);
+                let kind = fs::symlink_metadata(&abs).map(|m| m.file_type()).ok();
+                let is_dir = kind.is_some_and(|k| k.is_dir());
+                let tracked = index.tracked_under(&rel).chain(index.unmerged_under(&rel)).next().is_some();
+                if !opts.force && !tracked && !rel.is_empty() && ignore.is_ignored(&rel, is_dir)? {
+                    report.skipped.push(p.clone());
+                    continue;
+                }
+                matched.extend(index.tracked_under(&rel).chain(index.unmerged_under(&rel)).cloned());
+                if is_dir {
+                    for f in self.walk(&abs, &mut ignore)? {
+                        matched.push(self.to_repo_relative(&f)?);
+                    }
+                } else if kind.is_some() {
+                    matched.push(rel);
+                }
+            }
+            if matched.is_empty() {
+                return Err(Error::NoMatch { pathspec: p.display().to_string(), tracked: false });
+            }
+            candidates.extend(matched);
+        }
+
+        // 2) stage what's on disk and drop tracked paths that aren't any more
+        for rel in candidates {
+            if opts.update && !index.contains_key(&rel) {
+                continue; // -u: untracked files stay untracked
+            }
+            let path = root.join(&rel);
+            let change = match fs::symlink_metadata(&path) {
+                Ok(meta) if !meta.is_dir() => self.stage_file(&path, &mut index, opts.dry_run)?,
+                _ => {
+                    let resolved = index.conflicts.remove(&rel).is_some();
+                    index.remove(&rel).map(|_| Staged::Removed).or(resolved.then_some(Staged::Removed))
+                }
+            };
+            if let Some(change) = change {
+                report.changes.push((rel, change));
+            }
+        }
+
+        // 3) a dry run leaves the index alone (dropping the lock releases it)
+        if !opts.dry_run {
+            self.save_index(&index, lock)?;
+        }
+        Ok(report)
+    }
+
+    /// Unstage tracked `paths` and (unless `cached`) delete them from the worktree; returns
+    /// the removed paths. Directories need `recursive`. Without `force`, refuses to drop
+    /// content that isn't stored anywhere else. Removing an unmerged path resolves it.
+    pub fn rm(&self, paths: &[PathBuf], cached: bool, recursive: bool, force: bool) -> Result<Vec<String>> {
+        let lock = self.lock_index()?;
+        let mut index = self.load_index()?;
+        let head = self.head_tree()?;
+
+        // 1) expand each path into the tracked paths it names
+        let mut targets = BTreeSet::new();
+        for p in paths {
+            let rel = self.to_repo_relative(p)?;
+            let matched: BTreeSet<String> = index.tracked_under(&rel).chain(index.unmerged_under(&rel)).cloned().collect();
+            if matched.is_empty() {
+                return Err(Error::NoMatch { pathspec: p.display().to_string(), tracked: true });
+            }
+            if !recursive && matched.iter().any(|m| *m != rel) {
+                return Err(Error::Invalid(format!("not removing '{}' recursively without -r", p.display())));
+            }
+            targets.extend(matched);
+        }
+
+        // 2) safety: refuse to throw away content that isn't stored anywhere else (removing
+        //    an unmerged path resolves its conflict that way, and each side is in a commit)
+        if !force {
+            let mut at_risk = Vec::new();
+            for path in targets.iter().filter(|p| !index.conflicts.contains_key(*p)) {
+                let entry = &index[path];
+                let staged = FileEntry { mode: entry.mode, blob: entry.blob.clone() };
+                let local = self.worktree_entry(path, Some(entry))?.is_some_and(|w| w != staged);
+                let committed = head.get(path) == Some(&staged);
+                if local && !committed {
+                    at_risk.push(format!("{path} (staged content differs from both the file and HEAD)"));
+                } else if local && !cached {
+                    at_risk.push(format!("{path} (local modifications)"));
+                } else if !committed && !cached {
+                    at_risk.push(format!("{path} (changes staged in the index)"));
+                }
+            }
+            if !at_risk.is_empty() {
+                return Err(Error::WouldLoseChanges(at_risk));
+            }
+        }
+
+        // 3) unstage, then (without --cached) delete the files too
+        for path in &targets {
+            index.remove(path);
+            index.conflicts.remove(path);
+        }
+        self.save_index(&index, lock)?;
+        for path in &targets {
+            let dest = self.worktree().join(path);
+            if !cached && fs::symlink_metadata(&dest).is_ok_and(|m| !m.is_dir()) {
+                self.remove_tracked_file(&dest)?;
+            }
+        }
+        Ok(targets.into_iter().collect())
+    }
+
+    /// Move or rename a tracked file or directory, on disk and in the index.
+    pub fn mv(&self, src: &Path, dst: &Path, force: bool) -> Result<Move> {
+        let lock = self.lock_index()?;
+        let mut index = self.load_index()?;
+
+        // 1) resolve paths: moving into an existing directory keeps the source's name
+        let src_rel = self.to_repo_relative(src)?;
+        let src_abs = self.resolve(src);
+        let mut dst = dst.to_path_buf();
+        if fs::metadata(self.resolve(&dst)).is_ok_and(|m| m.is_dir()) {
+            let name = src.file_name().ok_or_else(|| Error::Invalid(format!("bad source: {}", src.display())))?;
+            dst = dst.join(name);
+        }
+        let dst_rel = self.to_repo_relative(&dst)?;
+        let dst_abs = self.resolve(&dst);
+        let moved: Vec<String> = index.tracked_under(&src_rel).cloned().collect();
+
+        // 2) safety: a tracked source, a destination that's free (or -f), and not into itself
+        let refuse = |msg: String| Err(Error::Invalid(msg));
+        if fs::symlink_metadata(&src_abs).is_err() {
+            return refuse(format!("bad source: {} does not exist", src.display()));
+        }
+        if src_rel.is_empty() || moved.is_empty() {
+            return refuse(format!("not under version control: {}", src.display()));
+        }
+        if dst_rel == src_rel || dst_rel.starts_with(&format!("{src_rel}/")) {
+            return refuse(format!("cannot move {} into itself", src.display()));
+        }
+        if !dst_abs.parent().is_some_and(|d| d.is_dir()) {
+            return refuse(format!("destination directory does not exist: {}", dst.display()));
+        }
+        if let Ok(meta) = fs::symlink_metadata(&dst_abs) {
+            if meta.is_dir() {
+                return refuse(format!("destination is a directory: {}", dst.display()));
+            }
+            if !force {
+                return refuse(format!("destination exists: {} (use -f to overwrite it)", dst.display()));
+            }
+        }
+
+        // 3) move on disk, then re-key the index entries (blob, mode and stat data carry over)
+        fs::rename(&src_abs, &dst_abs).with_context(|| format!("renaming {} to {}", src.display(), dst.display()))?;
+        let mut renamed = Vec::new();
+        for old in moved {
+            let entry = index.remove(&old).expect("listed from the index");
+            let new = format!("{dst_rel}{}", &old[src_rel.len()..]);
+            index.insert(new.clone(), entry);
+            renamed.push((old, new));
+        }
+        self.save_index(&index, lock)?;
+        Ok(Move { from: src_rel, to: dst_rel, renamed })
+    }
+}
@@ -0,0 +1,81 @@
+//! Annotated tags: objects of kind `tag` in the object store, laid out like git's
+//! (`object`/`type`/`tag`/`tagger` headers, a blank line, then the message).
+
+use crate::commit::{ident_line, parse_ident_line};
+use crate::error::{Error, Result};
+use crate::objects::ObjectStore;
+
+/// An annotated tag object.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Tag {
+    pub id: String,
+    /// The tagged commit.
+    pub object: String,
+    pub name: String,
+    pub tagger: String,
+    /// RFC 3339 timestamp with the tagger's local offset.
+    pub timestamp: String,
+    pub message: String,
+}
+
+impl Tag {
+    /// Build a tag of `commit` stamped with the current time and write it to `store`.
+    pub fn new(store: &ObjectStore, name: &str, commit: &str, tagger: String, message: &str) -> Result<Self> {
+        let now = chrono::Local::now().fixed_offset();
+        let mut text = format!("object {commit}\ntype commit\ntag {name}\ntagger {}\n\n{message}", ident_line(&tagger, &now));
+        if !text.ends_with('\n') {
+            text.push('\n');
+        }
+        let id = store.write("tag", text.as_bytes())?;
+        Ok(Tag {
+            id,
+            object: commit.to_string(),
+            name: name.to_string(),
+            tagger,
+            timestamp: now.to_rfc3339(),
+            message: message.to_string(),
+        })
+    }
+
+    /// Read tag object `id` back out of `store`.
+    pub fn read(store: &ObjectStore, id: &str) -> Result<Self> {
+        let (kind, data) = store.read(id)?;
+        if kind != "tag" {
+            return Err(Error::WrongObjectKind { id: id.to_string(), kind, expected: "tag" });
+        }
+        let corrupt = |reason: &str| Error::Corrupt { what: format!("tag object {id}"), reason: reason.to_string() };
+        let text = String::from_utf8(data).map_err(|_| corrupt("not UTF-8"))?;
+        let (headers, message) = text.split_once("\n\n").unwrap_or((text.as_str(), ""));
+
+        // 1) headers: one "<key> <value>" per line
+        let (mut object, mut name, mut tagger) = (None, None, None);
+        for line in headers.lines() {
+            match line.split_once(' ') {
+                Some(("object", v)) => object = Some(v),
+                Some(("tag", v)) => name = Some(v),
+                Some(("tagger", v)) => tagger = Some(v),
+                _ => {}
+            }
+        }
+        let object = object.ok_or_else(|| corrupt("no object header"))?;
+        let name = name.ok_or_else(|| corrupt("no tag header"))?;
+        let tagger = tagger.ok_or_else(|| corrupt("no tagger header"))?;
+
+        // 2) "Name <email> <unix seconds> <+hhmm>" -> ident + RFC 3339
+        let (ident, when) = parse_ident_line(tagger).ok_or_else(|| corrupt("bad tagger line"))?;
+
+        Ok(Tag {
+            id: id.to_string(),
+            object: object.to_string(),
+            name: name.to_string(),
+            tagger: ident.to_string(),
+            timestamp: when.to_rfc3339(),
+            message: message.to_string(),
+        })
+    }
+
+    /// First line of the message.
+    pub fn subject(&self) -> &str {
+        self.message.lines().next().unwrap_or("")
+    }
+}
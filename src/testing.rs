@@ -0,0 +1,38 @@
+//! Scratch repositories for unit tests.
+
+use std::fs;
+
+use crate::repository::{Config, Repository};
+use crate::stage::AddOptions;
+
+/// A new repository in a scratch directory of its own.
+pub(crate) fn scratch(name: &str) -> Repository {
+    scratch_with(name, Config::default())
+}
+
+pub(crate) fn scratch_with(name: &str, config: Config) -> Repository {
+    let dir = std::env::temp_dir().join(format!("mini-git-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    Repository::init(&dir.join(".minigit"), &dir, config).unwrap()
+}
+
+pub(crate) fn write(repo: &Repository, path: &str, text: &str) {
+    let dest = repo.worktree().join(path);
+    fs::create_dir_all(dest.parent().unwrap()).unwrap();
+    fs::write(dest, text).unwrap();
+}
+
+pub(crate) fn read(repo: &Repository, path: &str) -> String {
+    fs::read_to_string(repo.worktree().join(path)).unwrap()
+}
+
+/// Write `files`, delete `removed` (tracked paths), and commit everything.
+pub(crate) fn commit(repo: &Repository, files: &[(&str, &str)], removed: &[&str], message: &str) {
+    if !removed.is_empty() {
+        repo.rm(&removed.iter().map(Into::into).collect::<Vec<_>>(), false, true, true).unwrap();
+    }
+    files.iter().for_each(|(path, text)| write(repo, path, text));
+    repo.add(&[], AddOptions::default()).unwrap();
+    repo.commit(message.to_string()).unwrap();
+}
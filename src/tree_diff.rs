@@ -0,0 +1,128 @@
+//! Comparing two sides (a commit, the index or the working tree) file by file, for `diff`.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::diff::{self, Edit, Op};
+use crate::error::{Context, Result};
+use crate::index::{is_under, FileEntry, Snapshot};
+use crate::repository::Repository;
+use crate::stage::{is_pathspec_glob, pathspec_matches};
+use crate::worktree::read_worktree_file;
+
+/// One side of a diff: a snapshot, plus whether its contents are in the working tree
+/// (whose blob ids are only computed, never written) rather than the object store.
+#[derive(Clone, Debug)]
+pub struct DiffSide {
+    pub files: Snapshot,
+    in_worktree: bool,
+}
+
+impl DiffSide {
+    /// A snapshot whose blobs are all in the object store.
+    pub fn tree(files: Snapshot) -> Self {
+        DiffSide { files, in_worktree: false }
+    }
+
+    /// The tree of revision `rev`.
+    pub fn commit(repo: &Repository, rev: &str) -> Result<Self> {
+        Ok(DiffSide::tree(repo.find_commit(rev)?.snapshot()))
+    }
+
+    /// What's staged.
+    pub fn index(repo: &Repository) -> Result<Self> {
+        Ok(DiffSide::tree(repo.load_index()?.snapshot()))
+    }
+
+    /// Tracked files as they are on disk.
+    pub fn worktree(repo: &Repository) -> Result<Self> {
+        let files = repo.worktree_tree(&mut repo.load_index()?)?;
+        Ok(DiffSide { files, in_worktree: true })
+    }
+
+    /// The contents of `path` on this side.
+    pub fn read(&self, repo: &Repository, path: &str) -> Result<Vec<u8>> {
+        if self.in_worktree {
+            let file = repo.worktree().join(path);
+            let meta = fs::symlink_metadata(&file).with_context(|| format!("reading {}", file.display()))?;
+            read_worktree_file(&file, &meta)
+        } else {
+            repo.objects().read_blob(&self.files[path].blob)
+        }
+    }
+}
+
+/// A changed path: its entry on each side (`None` = absent) and the contents, if read.
+#[derive(Clone, Debug)]
+pub struct FileDiff {
+    pub path: String,
+    pub old: Option<FileEntry>,
+    pub new: Option<FileEntry>,
+    pub old_data: Vec<u8>,
+    pub new_data: Vec<u8>,
+}
+
+impl FileDiff {
+    /// git's one-letter status: A (added), D (deleted) or M (modified).
+    pub fn letter(&self) -> char {
+        match (&self.old, &self.new) {
+            (None, _) => 'A',
+            (_, None) => 'D',
+            _ => 'M',
+        }
+    }
+
+    pub fn is_binary(&self) -> bool {
+        diff::is_binary(&self.old_data) || diff::is_binary(&self.new_data)
+    }
+
+    /// Whether a patch or --stat has anything to say (whitespace-only edits may not).
+    pub fn shows_changes(&self, opts: &diff::Options) -> bool {
+        let (Some(o), Some(n)) = (&self.old, &self.new) else { return true };
+        o.mode != n.mode || self.is_binary() || self.script(opts).2.iter().any(|e| e.op != Op::Equal)
+    }
+
+    /// Old lines, new lines and the edit script between them.
+    pub fn script(&self, opts: &diff::Options) -> (Vec<&[u8]>, Vec<&[u8]>, Vec<Edit>) {
+        let (a, b) = (diff::split_lines(&self.old_data), diff::split_lines(&self.new_data));
+        let edits = diff::diff_lines(&a, &b, opts);
+        (a, b, edits)
+    }
+}
+
+impl Repository {
+    /// The paths that differ between `old` and `new`, in path order, limited to `pathspecs`
+    /// (files, directories or globs; empty means everything). Contents are only read
+    /// `with_data`, since listing names doesn't need them.
+    pub fn diff_trees(&self, old: &DiffSide, new: &DiffSide, pathspecs: &[PathBuf], with_data: bool) -> Result<Vec<FileDiff>> {
+        // 1) the pathspec, as repo-relative paths
+        let specs: Vec<(String, bool)> =
+            pathspecs.iter().map(|p| Ok((self.to_repo_relative(p)?, is_pathspec_glob(p)))).collect::<Result<_>>()?;
+        let wanted = |path: &str| {
+            specs.is_empty()
+                || specs.iter().any(|(rel, glob)| if *glob { pathspec_matches(rel, path) } else { is_under(path, rel) })
+        };
+
+        // 2) every path whose entry differs, with its contents on each side
+        let paths: BTreeSet<&String> = old.files.keys().chain(new.files.keys()).collect();
+        let mut out = Vec::new();
+        for path in paths {
+            let (o, n) = (old.files.get(path), new.files.get(path));
+            if o == n || !wanted(path) {
+                continue;
+            }
+            let read = |side: &DiffSide, e: Option<&FileEntry>| -> Result<Vec<u8>> {
+                if with_data && e.is_some() { side.read(self, path) } else { Ok(Vec::new()) }
+            };
+            out.push(FileDiff {
+                path: path.clone(),
+                old: o.cloned(),
+                new: n.cloned(),
+                old_data: read(old, o)?,
+                new_data: read(new, n)?,
+            });
+        }
+        Ok(out)
+    }
+}
@@ -0,0 +1,446 @@
+//! The working tree: walking and hashing it, comparing it with the index and HEAD
+//! (`status`), and rewriting it to match a commit (`checkout`).
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::commit::Commit;
+use crate::error::{Context, Error, Result};
+use crate::ignore::Ignore;
+use crate::index::{mode_of, Conflict, FileEntry, FileStat, Index, IndexEntry, Snapshot, MODE_EXEC, MODE_SYMLINK};
+use crate::refs::Head;
+use crate::repository::Repository;
+
+/// A worktree path's blob content: file bytes, or a symlink's target path.
+pub fn read_worktree_file(path: &Path, meta: &fs::Metadata) -> Result<Vec<u8>> {
+    if meta.file_type().is_symlink() {
+        let target = fs::read_link(path).with_context(|| format!("reading link {}", path.display()))?;
+        #[cfg(unix)]
+        return Ok(std::os::unix::ffi::OsStrExt::as_bytes(target.as_os_str()).to_vec());
+        #[cfg(not(unix))]
+        return Ok(target.to_string_lossy().into_owned().into_bytes());
+    }
+    fs::read(path).with_context(|| format!("reading {}", path.display()))
+}
+
+/// What changed between HEAD, the index and the working tree (paths are repo-relative, sorted).
+#[derive(Default, Debug)]
+pub struct Status {
+    pub staged_new: Vec<String>,
+    pub staged_modified: Vec<String>,
+    pub staged_deleted: Vec<String>,
+    pub unstaged_modified: Vec<String>,
+    pub deleted: Vec<String>,
+    pub untracked: Vec<String>,
+    /// Paths with unresolved merge conflicts (listed nowhere else).
+    pub unmerged: Vec<(String, Conflict)>,
+}
+
+impl Status {
+    pub fn is_clean(&self) -> bool {
+        self.staged_new.is_empty()
+            && self.staged_modified.is_empty()
+            && self.staged_deleted.is_empty()
+            && self.unstaged_modified.is_empty()
+            && self.deleted.is_empty()
+            && self.untracked.is_empty()
+            && self.unmerged.is_empty()
+    }
+}
+
+/// What `checkout` did.
+#[derive(Debug)]
+pub struct Checkout {
+    pub commit: Commit,
+    /// Paths written from the target commit.
+    pub restored: Vec<String>,
+    /// Tracked paths the target doesn't have, now deleted.
+    pub removed: Vec<String>,
+}
+
+impl Repository {
+    /// Every file under `dir` (absolute), skipping the repository directory and anything
+    /// `.minigitignore` excludes.
+    pub fn walk(&self, dir: &Path, ignore: &mut Ignore) -> Result<Vec<PathBuf>> {
+        let mut out = Vec::new();
+        let context = || format!("reading {}", dir.display());
+        for entry in fs::read_dir(dir).with_context(context)? {
+            let entry = entry.with_context(context)?;
+            let p = entry.path();
+            if p == self.git_dir() {
+                continue;
+            }
+            // file_type() doesn't follow symlinks: a link is recorded as a link, never walked into
+            let kind = entry.file_type().with_context(context)?;
+            let is_dir = kind.is_dir();
+            // parents were already checked on the way down, so only this entry's own rule matters
+            if ignore.rule_for(&self.to_repo_relative(&p)?, is_dir)?.is_some_and(|r| !r.negated) {
+                continue;
+            }
+            if is_dir {
+                out.extend(self.walk(&p, ignore)?);
+            } else if kind.is_file() || kind.is_symlink() {
+                out.push(p);
+            }
+        }
+        Ok(out)
+    }
+
+    /// Hash every tracked file as it is in the working tree: repo-relative path -> mode +
+    /// blob id (nothing is written; deleted files are left out). Files whose stat data
+    /// matches their index entry aren't read at all; entries that turn out unchanged
+    /// despite a stale stat get it refreshed.
+    pub fn worktree_tree(&self, index: &mut Index) -> Result<Snapshot> {
+        let mut out = Snapshot::new();
+        for (rel, e) in index.iter_mut() {
+            let f = self.worktree().join(rel);
+            let Ok(meta) = fs::symlink_metadata(&f) else { continue };
+            if meta.is_dir() {
+                continue;
+            }
+            if e.matches_stat(&meta) {
+                out.insert(rel.clone(), FileEntry { mode: e.mode, blob: e.blob.clone() });
+                continue;
+            }
+            let data = read_worktree_file(&f, &meta)?;
+            let entry = FileEntry { mode: mode_of(&meta), blob: self.objects().hash("blob", &data) };
+            if e.blob == entry.blob && e.mode == entry.mode {
+                e.stat = FileStat::from_metadata(&meta);
+            }
+            out.insert(rel.clone(), entry);
+        }
+        Ok(out)
+    }
+
+    /// Files in the working tree that `index` doesn't track and .minigitignore doesn't
+    /// exclude, sorted. Only their names are needed, so none is read.
+    pub fn untracked_files(&self, index: &Index) -> Result<Vec<String>> {
+        let root = self.worktree();
+        let mut out = Vec::new();
+        for f in self.walk(root, &mut Ignore::new(root))? {
+            let rel = self.to_repo_relative(&f)?;
+            if !index.contains_key(&rel) {
+                out.push(rel);
+            }
+        }
+        out.sort();
+        Ok(out)
+    }
+
+    /// Mode + blob id of one worktree path as it is now (`None` if it's gone), without
+    /// reading the file when `cached` stat data still matches.
+    pub fn worktree_entry(&self, rel: &str, cached: Option<&IndexEntry>) -> Result<Option<FileEntry>> {
+        let path = self.worktree().join(rel);
+        let Ok(meta) = fs::symlink_metadata(&path) else { return Ok(None) };
+        if meta.is_dir() {
+            return Ok(None);
+        }
+        if let Some(e) = cached
+            && e.matches_stat(&meta)
+        {
+            return Ok(Some(FileEntry { mode: e.mode, blob: e.blob.clone() }));
+        }
+        let data = read_worktree_file(&path, &meta)?;
+        Ok(Some(FileEntry { mode: mode_of(&meta), blob: self.objects().hash("blob", &data) }))
+    }
+
+    /// Remove `path`, then any parent directories it leaves empty (stopping at the worktree root).
+    pub(crate) fn remove_tracked_file(&self, path: &Path) -> Result<()> {
+        if fs::symlink_metadata(path).is_ok() {
+            fs::remove_file(path).with_context(|| format!("removing {}", path.display()))?;
+        }
+        let mut dir = path.parent();
+        while let Some(d) = dir {
+            if d == self.worktree() || fs::remove_dir(d).is_err() {
+                break; // not empty (or the root): leave it
+            }
+            dir = d.parent();
+        }
+        Ok(())
+    }
+
+    /// What would stand in the way of writing a file at repo-relative `path` once the tracked
+    /// paths `dropped` accepts are deleted: a file where one of its parent directories
+    /// belongs, or files inside a directory in its place. Ignored files don't count.
+    pub(crate) fn obstructions(&self, path: &str, dropped: impl Fn(&str) -> bool) -> Result<Vec<String>> {
+        let root = self.worktree();
+        let mut ignore = Ignore::new(root);
+        let mut out = Vec::new();
+        for (i, _) in path.match_indices('/') {
+            let dir = &path[..i];
+            if fs::symlink_metadata(root.join(dir)).is_ok_and(|m| !m.is_dir()) && !dropped(dir) && !ignore.is_ignored(dir, false)? {
+                out.push(dir.to_string());
+            }
+        }
+        let dest = root.join(path);
+        if fs::symlink_metadata(&dest).is_ok_and(|m| m.is_dir()) && !ignore.is_ignored(path, true)? {
+            for f in self.walk(&dest, &mut ignore)? {
+                let rel = self.to_repo_relative(&f)?;
+                if !dropped(&rel) {
+                    out.push(rel);
+                }
+            }
+        }
+        Ok(out)
+    }
+
+    /// Clear the way for writing a file at repo-relative `path` (see
+    /// [`Repository::obstructions`]): remove a file where a parent directory belongs or a
+    /// directory in its place, then create the parent directories.
+    pub(crate) fn make_room_for(&self, path: &str) -> Result<()> {
+        let root = self.worktree();
+        for (i, _) in path.match_indices('/') {
+            let dir = root.join(&path[..i]);
+            if fs::symlink_metadata(&dir).is_ok_and(|m| !m.is_dir()) {
+                fs::remove_file(&dir).with_context(|| format!("removing {}", dir.display()))?;
+            }
+        }
+        let dest = root.join(path);
+        if fs::symlink_metadata(&dest).is_ok_and(|m| m.is_dir()) {
+            fs::remove_dir_all(&dest).with_context(|| format!("removing {}", dest.display()))?;
+        }
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent).with_context(|| format!("creating {}", parent.display()))?;
+        }
+        Ok(())
+    }
+
+    /// Write one snapshot entry into the worktree: file contents plus permissions, or a symlink.
+    pub(crate) fn restore_file(&self, dest: &Path, entry: &FileEntry) -> Result<()> {
+        let data = self.objects().read_blob(&entry.blob)?;
+        // never write *through* an existing symlink (or onto a file we're replacing with one)
+        if fs::symlink_metadata(dest).is_ok_and(|m| m.file_type().is_symlink()) || entry.mode == MODE_SYMLINK {
+            let _ = fs::remove_file(dest);
+        }
+        if entry.mode == MODE_SYMLINK {
+            #[cfg(unix)]
+            {
+                use std::os::unix::ffi::OsStrExt;
+                let target = std::ffi::OsStr::from_bytes(&data);
+                std::os::unix::fs::symlink(target, dest).with_context(|| format!("creating symlink {}", dest.display()))?;
+            }
+            // no portable symlinks: fall back to a file holding the target, like git does
+            #[cfg(not(unix))]
+            fs::write(dest, &data).with_context(|| format!("writing {}", dest.display()))?;
+            return Ok(());
+        }
+        fs::write(dest, &data).with_context(|| format!("writing {}", dest.display()))?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let perm = if entry.mode == MODE_EXEC { 0o755 } else { 0o644 };
+            fs::set_permissions(dest, fs::Permissions::from_mode(perm))
+                .with_context(|| format!("setting permissions on {}", dest.display()))?;
+        }
+        Ok(())
+    }
+
+    /* -------- status -------- */
+
+    pub fn status(&self) -> Result<Status> {
+        let head = self.head_tree()?;
+
+        // Refresh stale stat data while we're at it, if nobody else holds the index
+        let lock = self.try_lock_index()?;
+        let mut index = self.load_index()?;
+        let before = index.clone();
+        let work = self.worktree_tree(&mut index)?;
+        let untracked = self.untracked_files(&index)?;
+        if let Some(lock) = lock
+            && index != before
+        {
+            self.save_index(&index, lock)?;
+        }
+
+        // Conflicted paths are listed apart from everything else until they're resolved.
+        let unmerged = index.conflicts.iter().map(|(p, c)| (p.clone(), c.clone())).collect();
+        let conflicted = |p: &String| index.conflicts.contains_key(p);
+        let head: Snapshot = head.into_iter().filter(|(p, _)| !conflicted(p)).collect();
+        let work: Snapshot = work.into_iter().filter(|(p, _)| !conflicted(p)).collect();
+        let index: Snapshot = index.snapshot().into_iter().filter(|(p, _)| !conflicted(p)).collect();
+        let mut st = Status { unmerged, ..Status::default() };
+
+        // HEAD vs index: what `commit` would record.
+        for (path, blob) in &index {
+            match head.get(path) {
+                None => st.staged_new.push(path.clone()),
+                Some(h) if h != blob => st.staged_modified.push(path.clone()),
+                Some(_) => {}
+            }
+        }
+        st.staged_deleted = head.keys().filter(|p| !index.contains_key(*p)).cloned().collect();
+
+        // index vs working tree: what `add` would pick up.
+        for (path, blob) in &index {
+            match work.get(path) {
+                None => st.deleted.push(path.clone()),
+                Some(w) if w != blob => st.unstaged_modified.push(path.clone()),
+                Some(_) => {}
+            }
+        }
+        st.untracked = untracked.into_iter().filter(|p| !conflicted(p)).collect();
+
+        Ok(st)
+    }
+
+    /* -------- checkout -------- */
+
+    /// Restore the working tree and index to commit `rev` and detach HEAD at it (`HEAD`
+    /// itself stays put). Without `force`, refuses if that would lose uncommitted changes.
+    pub fn checkout(&self, rev: &str, force: bool) -> Result<Checkout> {
+        let target = self.find_commit(rev)?;
+        let id = target.id.clone();
+        let done = self.checkout_tree(target, force)?;
+        if rev != "HEAD" && rev != "@" {
+            let message = format!("checkout: moving from {} to {rev}", self.head_description()?);
+            self.move_head(&Head::Detached(id), &message)?;
+        }
+        Ok(done)
+    }
+
+    /// Make the working tree and index match `target`, leaving HEAD for the caller to move.
+    /// An unfinished merge is dropped.
+    pub(crate) fn checkout_tree(&self, target: Commit, force: bool) -> Result<Checkout> {
+        let lock = self.lock_index()?;
+        let mut old_index = self.load_index()?;
+        if !force && !old_index.conflicts.is_empty() {
+            return Err(Error::Unmerged(old_index.conflicts.keys().cloned().collect()));
+        }
+        let work = self.worktree_tree(&mut old_index)?;
+        let index = old_index.snapshot();
+        let head = self.head_tree()?;
+        let target_files = target.snapshot();
+        // without --force, local changes to paths the checkout doesn't touch carry over
+        let carried = |path: &String| !force && index.get(path).is_some_and(|e| target_files.get(path) == Some(e));
+
+        // 1) safety: only paths the checkout actually changes matter
+        if !force {
+            let mut at_risk = Vec::new();
+            let mut ignore = Ignore::new(self.worktree());
+            let paths: BTreeSet<&String> = target_files.keys().chain(index.keys()).collect();
+            for path in paths {
+                let want = target_files.get(path);
+                let staged = index.get(path);
+                if want == staged {
+                    continue;
+                }
+                let dirty = match staged {
+                    // tracked: unstaged edits, or staged-but-uncommitted edits, would be lost
+                    Some(blob) => work.get(path) != Some(blob) || head.get(path) != Some(blob),
+                    // untracked file sitting where the target wants to write one (ignored files are expendable)
+                    None => {
+                        self.worktree_entry(path, None)?.is_some_and(|w| Some(&w) != want)
+                            && !ignore.is_ignored(path, false)?
+                    }
+                };
+                if dirty {
+                    at_risk.push(path.clone());
+                }
+            }
+            // a file or directory the target needs to replace with the other kind
+            let dropped = |p: &str| index.contains_key(p) && !target_files.contains_key(p);
+            for (path, want) in &target_files {
+                if index.get(path) != Some(want) {
+                    at_risk.extend(self.obstructions(path, dropped)?);
+                }
+            }
+            if !at_risk.is_empty() {
+                at_risk.sort();
+                at_risk.dedup();
+                return Err(Error::WouldOverwrite(at_risk));
+            }
+        }
+
+        // 2) delete tracked files the target doesn't have first, so a directory it replaces
+        //    with a file (or the other way round) is out of the way (a path only HEAD has was
+        //    unstaged with `rm --cached`: it's untracked now, so it stays, like in git)
+        let mut removed = Vec::new();
+        for path in index.keys() {
+            let dest = self.worktree().join(path);
+            if !target_files.contains_key(path) && fs::symlink_metadata(&dest).is_ok() {
+                self.remove_tracked_file(&dest)?;
+                removed.push(path.clone());
+            }
+        }
+
+        // 3) write every file in the target snapshot that differs on disk
+        let mut restored = Vec::new();
+        for (path, entry) in &target_files {
+            if work.get(path) == Some(entry) || carried(path) {
+                continue;
+            }
+            self.make_room_for(path)?;
+            self.restore_file(&self.worktree().join(path), entry)?;
+            restored.push(path.clone());
+        }
+
+        // 4) the index (with fresh stat data) now describes the target
+        let mut new_index = Index::new();
+        for (path, entry) in &target_files {
+            if carried(path) {
+                new_index.insert(path.clone(), old_index[path].clone());
+                continue;
+            }
+            let meta = fs::symlink_metadata(self.worktree().join(path)).with_context(|| format!("reading {path}"))?;
+            let stat = FileStat::from_metadata(&meta);
+            new_index.insert(path.clone(), IndexEntry { blob: entry.blob.clone(), mode: entry.mode, stat });
+        }
+        self.save_index(&new_index, lock)?;
+        self.clear_merge_state()?;
+
+        Ok(Checkout { commit: target, restored, removed })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{commit, read, scratch, write};
+
+    /// A file `a` on main~1 that main turns into a directory, plus `c/d`.
+    fn file_then_dir(name: &str) -> Repository {
+        let repo = scratch(name);
+        commit(&repo, &[("a", "file\n")], &[], "file");
+        commit(&repo, &[("a/b", "nested\n"), ("c/d", "new\n")], &["a"], "dir");
+        repo
+    }
+
+    #[test]
+    fn checkout_swaps_files_and_directories() {
+        let repo = file_then_dir("checkout-file-dir");
+        for force in [false, true] {
+            repo.checkout("main~1", force).unwrap();
+            assert_eq!(read(&repo, "a"), "file\n");
+            assert!(!repo.worktree().join("c").exists());
+            assert!(repo.status().unwrap().is_clean());
+
+            repo.switch("main", force).unwrap();
+            assert_eq!(read(&repo, "a/b"), "nested\n");
+            assert!(repo.status().unwrap().is_clean());
+        }
+        fs::remove_dir_all(repo.worktree()).unwrap();
+    }
+
+    #[test]
+    fn checkout_keeps_untracked_files_in_the_way_unless_forced() {
+        let repo = file_then_dir("checkout-file-dir-untracked");
+
+        // untracked files inside a directory the target replaces with a file
+        write(&repo, "a/untracked", "mine\n");
+        let err = repo.checkout("main~1", false).unwrap_err();
+        assert!(matches!(&err, Error::WouldOverwrite(p) if p == &["a/untracked"]), "{err}");
+        assert_eq!(read(&repo, "a/b"), "nested\n");
+        repo.checkout("main~1", true).unwrap();
+        assert_eq!(read(&repo, "a"), "file\n");
+
+        // an untracked file where the target needs a directory
+        write(&repo, "c", "mine\n");
+        let err = repo.switch("main", false).unwrap_err();
+        assert!(matches!(&err, Error::WouldOverwrite(p) if p == &["c"]), "{err}");
+        assert_eq!(read(&repo, "c"), "mine\n");
+        repo.switch("main", true).unwrap();
+        assert_eq!(read(&repo, "c/d"), "new\n");
+        fs::remove_dir_all(repo.worktree()).unwrap();
+    }
+}